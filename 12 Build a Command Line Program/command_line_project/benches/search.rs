@@ -0,0 +1,66 @@
+use std::{env, fs, io, process};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use minigrep::Config;
+
+// About 5 MB of lines that look alike, with the rare query in every 10 000th line.
+fn corpus() -> String {
+    let mut contents = String::new();
+
+    for number in 0..100_000 {
+        if number % 10_000 == 0 {
+            contents.push_str(&format!("{number}: Who are you? I'm nobody! Are you nobody, too?\n"));
+        } else {
+            contents.push_str(&format!("{number}: Rust is safe, fast and productive. Pick three.\n"));
+        }
+    }
+
+    contents
+}
+
+// The same queries through the old line by line search and the memchr scan of the library's minigrep::search.
+// A rare query is where the scan should win the most, a query on every line is where it has the least to skip.
+fn compare(c: &mut Criterion) {
+    let contents = corpus();
+    let mut group = c.benchmark_group("search");
+    group.throughput(Throughput::Bytes(contents.len() as u64));
+
+    for (name, query) in [("rare", "nobody"), ("every line", "productive"), ("missing", "somebody")] {
+        group.bench_with_input(BenchmarkId::new("by line", name), query, |b, query| {
+            b.iter(|| minigrep::search_by_line(black_box(query), black_box(&contents)))
+        });
+        group.bench_with_input(BenchmarkId::new("memchr scan", name), query, |b, query| {
+            b.iter(|| minigrep::search(black_box(query), black_box(&contents)))
+        });
+    }
+
+    group.finish();
+}
+
+// What the command line does with a file: a plain query goes through the same memchr scan, and --regex makes it
+// search line by line. The queries have no special characters, so both find the same lines.
+fn command_line(c: &mut Criterion) {
+    let contents = corpus();
+    let file = env::temp_dir().join(format!("minigrep_bench_{}.txt", process::id()));
+    fs::write(&file, &contents).unwrap();
+    let path = file.to_str().unwrap();
+
+    let mut group = c.benchmark_group("command line");
+    group.throughput(Throughput::Bytes(contents.len() as u64));
+
+    for (name, query) in [("rare", "nobody"), ("every line", "productive"), ("missing", "somebody")] {
+        for (how, flags) in [("memchr scan", &[][..]), ("by line", &["--regex"][..])] {
+            let args: Vec<String> = ["minigrep", query, path].iter().chain(flags).map(|arg| arg.to_string()).collect();
+            let config = Config::build(&args).unwrap();
+            group.bench_function(BenchmarkId::new(how, name), |b| {
+                b.iter(|| minigrep::search_to(black_box(config.clone()), false, &mut io::sink()).unwrap())
+            });
+        }
+    }
+
+    group.finish();
+    let _ = fs::remove_file(&file);
+}
+
+criterion_group!(benches, compare, command_line);
+criterion_main!(benches);
@@ -0,0 +1,323 @@
+use std::error::Error;
+use std::fmt;
+
+// Every flag minigrep understands. The parser and the --help screen are both generated from this table,
+// so adding a flag here is enough to make it parse and show up in the usage.
+pub struct Flag {
+    pub short: Option<char>,
+    pub long: &'static str,
+    // Name of the value shown in the usage, None for flags that are just switches.
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+pub const FLAGS: &[Flag] = &[
+    Flag { short: Some('i'), long: "ignore-case", value: None, help: "Ignore case distinctions in the query" },
+    Flag { short: None, long: "case-fold", value: Some("MODE"), help: "How -i compares text: 'unicode' (default) or 'lowercase'" },
+    Flag { short: Some('n'), long: "line-number", value: None, help: "Prefix each matching line with its line number" },
+    Flag { short: Some('b'), long: "byte-offset", value: None, help: "Prefix each matching line with its byte offset" },
+    Flag { short: Some('A'), long: "after-context", value: Some("NUM"), help: "Print NUM lines of context after each match" },
+    Flag { short: Some('B'), long: "before-context", value: Some("NUM"), help: "Print NUM lines of context before each match" },
+    Flag { short: Some('C'), long: "context", value: Some("NUM"), help: "Print NUM lines of context around each match" },
+    Flag { short: Some('c'), long: "count", value: None, help: "Print only the number of matching lines" },
+    Flag { short: Some('l'), long: "files-with-matches", value: None, help: "Print only the names of files with a match" },
+    Flag { short: Some('L'), long: "files-without-match", value: None, help: "Print only the names of files without a match" },
+    Flag { short: Some('v'), long: "invert-match", value: None, help: "Select the lines that don't match" },
+    Flag { short: Some('m'), long: "max-count", value: Some("NUM"), help: "Stop reading a file after NUM matching lines" },
+    Flag { short: None, long: "output", value: Some("FORMAT"), help: "Print the results as 'text' or 'json' (one object per line)" },
+    Flag { short: None, long: "color", value: Some("WHEN"), help: "Highlight matches: 'auto' (default), 'always' or 'never'" },
+    Flag { short: None, long: "regex", value: None, help: "Treat the query as a regular expression" },
+    Flag { short: Some('e'), long: "regexp", value: Some("PATTERN"), help: "Search for PATTERN, can be given many times (the query is then the path)" },
+    Flag { short: Some('f'), long: "file", value: Some("FILE"), help: "Search for every pattern in FILE, one per line" },
+    Flag { short: None, long: "expr", value: None, help: "Treat the query as an expression: a AND NOT (b OR \"c d\")" },
+    Flag { short: None, long: "fuzzy", value: Some("NUM"), help: "Match text within NUM typos of the query, closest lines first" },
+    Flag { short: None, long: "replace", value: Some("TEXT"), help: "Print matching lines with the matches replaced ($1 refers to a regex group)" },
+    Flag { short: None, long: "in-place", value: None, help: "Write the replacements back to the files (needs --replace)" },
+    Flag { short: None, long: "backup", value: Some("SUFFIX"), help: "Keep the original of every edited file as FILE+SUFFIX" },
+    Flag { short: None, long: "dry-run", value: None, help: "Print what --in-place would change as a diff instead" },
+    Flag { short: Some('j'), long: "jobs", value: Some("NUM"), help: "Search NUM files at the same time (directories only)" },
+    Flag { short: None, long: "include", value: Some("GLOB"), help: "Only search files matching GLOB (directories only)" },
+    Flag { short: None, long: "exclude", value: Some("GLOB"), help: "Skip files and directories matching GLOB" },
+    Flag { short: Some('z'), long: "search-zip", value: None, help: "Search inside .gz, .bz2, .xz and .zst files" },
+    Flag { short: None, long: "encoding", value: Some("ENC"), help: "Read the input as ENC (latin1, windows-1252, shift_jis...) unless it has a BOM" },
+    Flag { short: None, long: "lossy", value: None, help: "Replace invalid UTF-8 instead of treating the input as binary" },
+    Flag { short: None, long: "hidden", value: None, help: "Search hidden files and directories too (directories only)" },
+    Flag { short: None, long: "no-ignore", value: None, help: "Don't skip the files .gitignore and .ignore files ignore" },
+    Flag { short: None, long: "index", value: None, help: "Skip the files the index says can't match (see 'minigrep index build')" },
+    Flag { short: None, long: "interactive", value: None, help: "Type the query in a terminal UI, the results update as you type" },
+    Flag { short: None, long: "print-config", value: None, help: "Print the configuration from the files, environment and flags, and exit" },
+    Flag { short: Some('h'), long: "help", value: None, help: "Print this help and exit" },
+];
+
+// Errors are typed, so the caller can tell a help request apart from an actual mistake.
+#[derive(Debug, PartialEq)]
+pub enum ArgsError {
+    HelpRequested,
+    UnknownFlag(String),
+    MissingValue(String),
+    UnexpectedValue(String),
+    // The flag and the value it was given.
+    InvalidValue(String, String),
+    MissingQuery,
+    UnexpectedArgument(String),
+    // A flag that only works together with another one, and that other flag.
+    Requires(String, String),
+    // The pattern file and why it couldn't be read.
+    UnreadableFile(String, String),
+    // A config file or variable that couldn't be used, and why.
+    InvalidConfig(String, String),
+    // Two flags that can't be used at the same time.
+    Conflicts(String, String),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::HelpRequested => write!(f, "help requested"),
+            ArgsError::UnknownFlag(flag) => write!(f, "unknown flag '{flag}'"),
+            ArgsError::MissingValue(flag) => write!(f, "flag '{flag}' needs a value"),
+            ArgsError::UnexpectedValue(flag) => write!(f, "flag '{flag}' doesn't take a value"),
+            ArgsError::InvalidValue(flag, value) => write!(f, "invalid value '{value}' for flag '{flag}'"),
+            ArgsError::MissingQuery => write!(f, "didn't get a query string"),
+            ArgsError::UnexpectedArgument(arg) => write!(f, "unexpected argument '{arg}'"),
+            ArgsError::Requires(flag, other) => write!(f, "flag '{flag}' only works together with '{other}'"),
+            ArgsError::UnreadableFile(file, error) => write!(f, "can't read patterns from '{file}': {error}"),
+            ArgsError::InvalidConfig(source, error) => write!(f, "invalid {source}: {error}"),
+            ArgsError::Conflicts(flag, other) => write!(f, "flags '{flag}' and '{other}' can't be used together"),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+// A flag that was found on the command line, named by its long name so the caller only needs one match arm per flag.
+#[derive(Debug, PartialEq)]
+pub struct Opt {
+    pub name: &'static str,
+    pub value: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Parsed {
+    pub opts: Vec<Opt>,
+    pub positional: Vec<String>,
+}
+
+// Parses the arguments without the program name. Flags keep their order, so a later flag can overwrite an earlier one.
+pub fn parse(args: impl Iterator<Item = String>) -> Result<Parsed, ArgsError> {
+    let mut opts = Vec::new();
+    let mut positional = Vec::new();
+    let mut args = args.peekable();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            // Everything after "--" is positional, which is how a query starting with "-" can be searched.
+            positional.extend(args.by_ref());
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            // Every switch can be turned off with --no-<switch>, so the command line can undo a config file.
+            // --help is the exception, asking for no help doesn't mean anything.
+            if let Some(flag) = name.strip_prefix("no-").and_then(switch).filter(|flag| flag.long != "help") {
+                if value.is_some() {
+                    return Err(ArgsError::UnexpectedValue(format!("--{name}")));
+                }
+                opts.push(Opt { name: flag.long, value: Some(String::from("false")) });
+                continue;
+            }
+
+            let flag = FLAGS
+                .iter()
+                .find(|flag| flag.long == name)
+                .ok_or_else(|| ArgsError::UnknownFlag(format!("--{name}")))?;
+
+            let value = match (flag.value, value) {
+                (Some(_), Some(value)) => Some(value),
+                (Some(_), None) => Some(args.next().ok_or_else(|| ArgsError::MissingValue(format!("--{name}")))?),
+                (None, Some(_)) => return Err(ArgsError::UnexpectedValue(format!("--{name}"))),
+                (None, None) => None,
+            };
+            opts.push(Opt { name: flag.long, value });
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            // Short flags can be combined ("-in"), a flag that takes a value eats the rest of the group ("-A3").
+            let mut chars = arg[1..].chars();
+
+            while let Some(short) = chars.next() {
+                let flag = FLAGS
+                    .iter()
+                    .find(|flag| flag.short == Some(short))
+                    .ok_or_else(|| ArgsError::UnknownFlag(format!("-{short}")))?;
+
+                let value = if flag.value.is_some() {
+                    let rest: String = chars.by_ref().collect();
+                    if rest.is_empty() {
+                        Some(args.next().ok_or_else(|| ArgsError::MissingValue(format!("-{short}")))?)
+                    } else {
+                        Some(rest)
+                    }
+                } else {
+                    None
+                };
+                opts.push(Opt { name: flag.long, value });
+            }
+        } else {
+            // A lone "-" is positional too, it's the usual name for stdin.
+            positional.push(arg);
+        }
+    }
+
+    if opts.iter().any(|opt| opt.name == "help") {
+        return Err(ArgsError::HelpRequested);
+    }
+
+    Ok(Parsed { opts, positional })
+}
+
+fn switch(name: &str) -> Option<&'static Flag> {
+    FLAGS.iter().find(|flag| flag.long == name && flag.value.is_none())
+}
+
+// The usage screen is generated from FLAGS, so it can't get out of date.
+pub fn usage() -> String {
+    let mut usage = String::from("Usage: minigrep [OPTIONS] [--] <QUERY> [PATH]\n");
+    usage.push_str("       minigrep [OPTIONS] -e <PATTERN>... [PATH]\n");
+    usage.push_str("       minigrep [OPTIONS] --interactive [PATH]\n");
+    usage.push_str("       minigrep index build [DIR]\n\n");
+    usage.push_str("Searches standard input when PATH is '-' or missing.\n\nOptions:\n");
+
+    for flag in FLAGS {
+        let short = match flag.short {
+            Some(short) => format!("-{short}, "),
+            None => String::from("    "),
+        };
+        let long = match flag.value {
+            Some(value) => format!("--{} <{value}>", flag.long),
+            None => format!("--{}", flag.long),
+        };
+        usage.push_str(&format!("  {short}{long:<24} {}\n", flag.help));
+    }
+
+    usage.push_str("\nEvery switch can be turned off with --no-<switch>, for example --no-ignore-case.\n");
+    usage.push_str("Options can also be set in ~/.config/minigrep/config.toml, a .minigreprc of the project\n");
+    usage.push_str("or MINIGREP_<OPTION> variables, see --print-config.\n");
+
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    fn opt(name: &'static str, value: Option<&str>) -> Opt {
+        Opt { name, value: value.map(String::from) }
+    }
+
+    #[test]
+    fn positional_only() {
+        let parsed = parse(args(&["duct", "poem.txt"])).unwrap();
+
+        assert!(parsed.opts.is_empty());
+        assert_eq!(vec!["duct", "poem.txt"], parsed.positional);
+    }
+
+    #[test]
+    fn short_and_long_flags() {
+        let short = parse(args(&["-i", "duct", "poem.txt"])).unwrap();
+        let long = parse(args(&["duct", "--ignore-case", "poem.txt"])).unwrap();
+
+        assert_eq!(vec![opt("ignore-case", None)], short.opts);
+        assert_eq!(short, long);
+    }
+
+    #[test]
+    fn combined_short_flags() {
+        let parsed = parse(args(&["-inc", "duct", "poem.txt"])).unwrap();
+
+        assert_eq!(
+            vec![opt("ignore-case", None), opt("line-number", None), opt("count", None)],
+            parsed.opts
+        );
+    }
+
+    #[test]
+    fn double_dash_ends_flags() {
+        let parsed = parse(args(&["-n", "--", "-i", "poem.txt"])).unwrap();
+
+        assert_eq!(vec![opt("line-number", None)], parsed.opts);
+        assert_eq!(vec!["-i", "poem.txt"], parsed.positional);
+    }
+
+    #[test]
+    fn single_dash_is_positional() {
+        let parsed = parse(args(&["duct", "-"])).unwrap();
+
+        assert_eq!(vec!["duct", "-"], parsed.positional);
+    }
+
+    #[test]
+    fn long_flag_values() {
+        let separate = parse(args(&["--include", "*.rs", "fn", "src"])).unwrap();
+        let joined = parse(args(&["--include=*.rs", "fn", "src"])).unwrap();
+
+        assert_eq!(vec![opt("include", Some("*.rs"))], separate.opts);
+        assert_eq!(separate, joined);
+    }
+
+    #[test]
+    fn short_flag_values() {
+        let separate = parse(args(&["-nA", "3", "fn", "src"])).unwrap();
+        let joined = parse(args(&["-nA3", "fn", "src"])).unwrap();
+
+        assert_eq!(vec![opt("line-number", None), opt("after-context", Some("3"))], separate.opts);
+        assert_eq!(separate, joined);
+    }
+
+    #[test]
+    fn unknown_flags() {
+        assert_eq!(Err(ArgsError::UnknownFlag("--nope".to_string())), parse(args(&["--nope"])));
+        assert_eq!(Err(ArgsError::UnknownFlag("-q".to_string())), parse(args(&["-iq"])));
+    }
+
+    #[test]
+    fn missing_and_unexpected_values() {
+        assert_eq!(Err(ArgsError::MissingValue("--include".to_string())), parse(args(&["--include"])));
+        assert_eq!(
+            Err(ArgsError::UnexpectedValue("--count".to_string())),
+            parse(args(&["--count=yes"]))
+        );
+    }
+
+    #[test]
+    fn switches_can_be_turned_off() {
+        let parsed = parse(args(&["-i", "--no-ignore-case", "duct"])).unwrap();
+
+        assert_eq!(vec![opt("ignore-case", None), opt("ignore-case", Some("false"))], parsed.opts);
+        // Only switches have a --no- version.
+        assert_eq!(Err(ArgsError::UnknownFlag("--no-context".to_string())), parse(args(&["--no-context"])));
+        assert_eq!(Err(ArgsError::UnknownFlag("--no-help".to_string())), parse(args(&["--no-help"])));
+        assert_eq!(Err(ArgsError::UnexpectedValue("--no-count".to_string())), parse(args(&["--no-count=yes"])));
+        // --no-ignore is a flag of its own, there is no --ignore switch it would turn off.
+        assert_eq!(vec![opt("no-ignore", None)], parse(args(&["--no-ignore"])).unwrap().opts);
+    }
+
+    #[test]
+    fn help_wins_over_everything_else() {
+        assert_eq!(Err(ArgsError::HelpRequested), parse(args(&["-h"])));
+        assert_eq!(Err(ArgsError::HelpRequested), parse(args(&["-ni", "--help", "duct"])));
+    }
+
+    #[test]
+    fn usage_lists_every_flag() {
+        let usage = usage();
+
+        for flag in FLAGS {
+            assert!(usage.contains(&format!("--{}", flag.long)));
+        }
+    }
+}
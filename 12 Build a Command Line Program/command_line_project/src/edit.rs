@@ -0,0 +1,212 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::input;
+use crate::{Config, Matcher};
+
+// Replaces every match in the file (--in-place). -v and -m are about choosing lines to print, so they don't change what is replaced.
+// The new contents are written to a temporary file next to the original, which is then renamed over it.
+// A rename is atomic, so the file is either the old one or the new one, even if minigrep is stopped in the middle.
+// With --dry-run nothing is written, the changes are printed as a diff instead.
+// Returns whether anything was replaced, a file without matches isn't touched at all.
+pub fn edit_file(config: &Config, matcher: &Matcher, file: &Path, out: &mut impl Write) -> io::Result<bool> {
+    let replacement = config.replace.as_deref().unwrap_or_default();
+    let mut reader = BufReader::new(File::open(file)?);
+
+    // Binary files would be broken by the rewrite, so they are skipped like when searching.
+    if input::is_binary(&mut reader)? {
+        return Ok(false);
+    }
+
+    let temp = temp_path(file);
+    let mut writer = if config.dry_run {
+        None
+    } else {
+        Some(BufWriter::new(OpenOptions::new().write(true).create_new(true).open(&temp)?))
+    };
+
+    let changed = match rewrite(matcher, replacement, reader, file, writer.as_mut(), config.dry_run, out) {
+        Ok(changed) => changed,
+        Err(e) => {
+            if writer.is_some() {
+                let _ = fs::remove_file(&temp);
+            }
+            return Err(e);
+        }
+    };
+
+    let Some(writer) = writer else {
+        return Ok(changed);
+    };
+    if !changed {
+        fs::remove_file(&temp)?;
+        return Ok(false);
+    }
+
+    let result = finish(config, writer, file, &temp);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result.map(|_| true)
+}
+
+// Copies the lines to the writer with the matches replaced. The line endings are kept as they were,
+// so a file with "\r\n" endings keeps them. The diff only shows the lines that changed.
+fn rewrite(
+    matcher: &Matcher,
+    replacement: &str,
+    mut reader: impl BufRead,
+    file: &Path,
+    mut writer: Option<&mut BufWriter<File>>,
+    dry_run: bool,
+    out: &mut impl Write,
+) -> io::Result<bool> {
+    let mut changed = false;
+    let mut raw = Vec::new();
+    let mut index = 0;
+
+    while reader.read_until(b'\n', &mut raw)? > 0 {
+        let text_len = raw.len() - line_ending(&raw).len();
+        let text = std::str::from_utf8(&raw[..text_len]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let (replaced, ranges) = matcher.replace(text, replacement);
+
+        if !ranges.is_empty() && replaced != text {
+            if dry_run {
+                if !changed {
+                    writeln!(out, "--- {}", file.display())?;
+                    writeln!(out, "+++ {}", file.display())?;
+                }
+                // One hunk per line, "-3 +3" means one line at line 3 in both files.
+                writeln!(out, "@@ -{0} +{0} @@", index + 1)?;
+                writeln!(out, "-{text}")?;
+                writeln!(out, "+{replaced}")?;
+            }
+            changed = true;
+        }
+
+        if let Some(writer) = writer.as_mut() {
+            writer.write_all(replaced.as_bytes())?;
+            writer.write_all(line_ending(&raw))?;
+        }
+
+        raw.clear();
+        index += 1;
+    }
+
+    Ok(changed)
+}
+
+// Puts the new file in place, after saving the original with the backup suffix if one was given.
+fn finish(config: &Config, writer: BufWriter<File>, file: &Path, temp: &Path) -> io::Result<()> {
+    let written = writer.into_inner().map_err(|e| e.into_error())?;
+    // The data has to be on the disk before the rename, otherwise a crash could leave an empty file behind the new name.
+    written.sync_all()?;
+    fs::set_permissions(temp, fs::metadata(file)?.permissions())?;
+
+    // The backup is a copy, so the original stays where it is until the rename replaces it.
+    if let Some(suffix) = &config.backup {
+        let mut backup = file.as_os_str().to_owned();
+        backup.push(suffix);
+        fs::copy(file, backup)?;
+    }
+
+    fs::rename(temp, file)
+}
+
+fn line_ending(raw: &[u8]) -> &[u8] {
+    if raw.ends_with(b"\r\n") {
+        &raw[raw.len() - 2..]
+    } else if raw.ends_with(b"\n") {
+        &raw[raw.len() - 1..]
+    } else {
+        &[]
+    }
+}
+
+// The temporary file has to be in the same directory, a rename can't move a file to another file system.
+fn temp_path(file: &Path) -> PathBuf {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    file.with_file_name(format!(".{name}.minigrep-{}.tmp", std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn config(args: &[&str]) -> Config {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Config::build_layered(&args, Vec::new()).unwrap()
+    }
+
+    // The directory has to live as long as the file is used, it's removed when it's dropped.
+    fn temp_file(name: &str, contents: &str) -> (TempDir, PathBuf) {
+        let dir = TempDir::new(&format!("edit_{name}"));
+
+        let file = dir.join("poem.txt");
+        fs::write(&file, contents).unwrap();
+        (dir, file)
+    }
+
+    #[test]
+    fn replaces_in_place_with_backup() {
+        let (_dir, file) = temp_file("backup", "I'm nobody!\r\nWho are you?\nAre you nobody, too?");
+        let path = file.to_str().unwrap();
+        let config = config(&["minigrep", "--replace", "somebody", "--in-place", "--backup", ".bak", "nobody", path]);
+        let matcher = Matcher::build(&config).unwrap();
+
+        let mut out = Vec::new();
+        assert!(edit_file(&config, &matcher, &file, &mut out).unwrap());
+
+        assert!(out.is_empty());
+        assert_eq!("I'm somebody!\r\nWho are you?\nAre you somebody, too?", fs::read_to_string(&file).unwrap());
+        assert_eq!(
+            "I'm nobody!\r\nWho are you?\nAre you nobody, too?",
+            fs::read_to_string(format!("{path}.bak")).unwrap()
+        );
+        // Only the file and its backup are left, the temporary file was renamed.
+        assert_eq!(2, fs::read_dir(file.parent().unwrap()).unwrap().count());
+    }
+
+    #[test]
+    fn regex_groups() {
+        let (_dir, file) = temp_file("groups", "fn main() {}\nfn run() {}\n");
+        let path = file.to_str().unwrap();
+        let config = config(&["minigrep", "--regex", "--replace", "pub fn ${name}", "--in-place", r"fn (?<name>\w+)", path]);
+        let matcher = Matcher::build(&config).unwrap();
+
+        assert!(edit_file(&config, &matcher, &file, &mut Vec::new()).unwrap());
+        assert_eq!("pub fn main() {}\npub fn run() {}\n", fs::read_to_string(&file).unwrap());
+    }
+
+    #[test]
+    fn dry_run_prints_a_diff() {
+        let contents = "safe, fast, productive.\nPick three.\nTrust me.";
+        let (_dir, file) = temp_file("dry_run", contents);
+        let path = file.to_str().unwrap();
+        let config = config(&["minigrep", "--replace", "u", "--in-place", "--dry-run", "-i", "T", path]);
+        let matcher = Matcher::build(&config).unwrap();
+
+        let mut out = Vec::new();
+        assert!(edit_file(&config, &matcher, &file, &mut out).unwrap());
+
+        assert_eq!(
+            format!("--- {path}\n+++ {path}\n@@ -1 +1 @@\n-safe, fast, productive.\n+safe, fasu, producuive.\n@@ -2 +2 @@\n-Pick three.\n+Pick uhree.\n@@ -3 +3 @@\n-Trust me.\n+urusu me.\n"),
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(contents, fs::read_to_string(&file).unwrap());
+        assert_eq!(1, fs::read_dir(file.parent().unwrap()).unwrap().count());
+    }
+
+    #[test]
+    fn files_without_matches_are_left_alone() {
+        let (_dir, file) = temp_file("untouched", "Pick three.");
+        let path = file.to_str().unwrap();
+        let config = config(&["minigrep", "--replace", "four", "--in-place", "--backup", ".bak", "five", path]);
+        let matcher = Matcher::build(&config).unwrap();
+
+        assert!(!edit_file(&config, &matcher, &file, &mut Vec::new()).unwrap());
+        assert_eq!(1, fs::read_dir(file.parent().unwrap()).unwrap().count());
+    }
+}
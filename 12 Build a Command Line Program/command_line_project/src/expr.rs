@@ -0,0 +1,266 @@
+use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+// A boolean query like "error AND NOT (timeout OR retry)". The words in it are the patterns.
+// They are kept in a list and the tree only has their index, so every pattern is searched once per line,
+// however many times the expression uses it.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Pattern(usize),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    // matched[i] tells if pattern i was found in the line.
+    pub fn eval(&self, matched: &[bool]) -> bool {
+        match self {
+            Expr::Pattern(index) => matched[*index],
+            Expr::Not(expr) => !expr.eval(matched),
+            Expr::And(left, right) => left.eval(matched) && right.eval(matched),
+            Expr::Or(left, right) => left.eval(matched) || right.eval(matched),
+        }
+    }
+
+    // Marks the patterns that are used without a NOT in front of them. Those are the ones worth highlighting,
+    // a pattern after NOT didn't help the line to match even when it was found.
+    pub fn mark_positive(&self, negated: bool, positive: &mut [bool]) {
+        match self {
+            Expr::Pattern(index) => positive[*index] |= !negated,
+            Expr::Not(expr) => expr.mark_positive(!negated, positive),
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                left.mark_positive(negated, positive);
+                right.mark_positive(negated, positive);
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ExprError {
+    pub expression: String,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid expression '{}': {}", self.expression, self.message)
+    }
+}
+
+impl Error for ExprError {}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Word(String),
+}
+
+// Parses the expression and adds its patterns to the list, a pattern that is already in the list is reused.
+//
+// AND binds tighter than OR, and NOT tighter than both, so "a OR b AND NOT c" is "a OR (b AND (NOT c))".
+// The operators are only operators in capitals, "and" is just a word. Text with spaces or parentheses
+// in it can be searched by quoting it: "\"out of memory\" OR OOM".
+pub fn parse(expression: &str, patterns: &mut Vec<String>) -> Result<Expr, ExprError> {
+    let error = |message: &str| ExprError { expression: expression.to_string(), message: message.to_string() };
+
+    let tokens = tokenize(expression).map_err(&error)?;
+    let mut parser = Parser { tokens: tokens.into_iter().peekable(), patterns };
+    let expr = parser.or().map_err(&error)?;
+
+    match parser.tokens.next() {
+        None => Ok(expr),
+        Some(Token::Close) => Err(error("')' without a '(' before it")),
+        // Two words next to each other would be a guess between AND and a phrase, so neither is guessed.
+        Some(_) => Err(error("expected AND or OR between the words, quote the text if it has spaces")),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, &'static str> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => quoted.push(c),
+                        None => return Err("a quote is missing its closing '\"'"),
+                    }
+                }
+                tokens.push(Token::Word(quoted));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+
+                tokens.push(match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Peekable<IntoIter<Token>>,
+    patterns: &'a mut Vec<String>,
+}
+
+impl Parser<'_> {
+    fn or(&mut self) -> Result<Expr, &'static str> {
+        let mut expr = self.and()?;
+
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, &'static str> {
+        let mut expr = self.not()?;
+
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, &'static str> {
+        if self.tokens.next_if_eq(&Token::Not).is_some() {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+
+        match self.tokens.next() {
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("a '(' is missing its closing ')'"),
+                }
+            }
+            Some(Token::Word(word)) => {
+                let index = match self.patterns.iter().position(|pattern| *pattern == word) {
+                    Some(index) => index,
+                    None => {
+                        self.patterns.push(word);
+                        self.patterns.len() - 1
+                    }
+                };
+                Ok(Expr::Pattern(index))
+            }
+            Some(Token::Close) => Err("expected a word before ')'"),
+            Some(_) => Err("AND and OR need a word on both sides"),
+            None => Err("the expression ended too early"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(expression: &str) -> (Expr, Vec<String>) {
+        let mut patterns = Vec::new();
+        let expr = parse(expression, &mut patterns).unwrap();
+        (expr, patterns)
+    }
+
+    fn error(expression: &str) -> String {
+        parse(expression, &mut Vec::new()).unwrap_err().message
+    }
+
+    fn pattern(index: usize) -> Box<Expr> {
+        Box::new(Expr::Pattern(index))
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let (expr, patterns) = parsed("a OR b AND NOT c");
+
+        assert_eq!(vec!["a", "b", "c"], patterns);
+        assert_eq!(
+            Expr::Or(pattern(0), Box::new(Expr::And(pattern(1), Box::new(Expr::Not(pattern(2)))))),
+            expr
+        );
+    }
+
+    #[test]
+    fn parentheses() {
+        let (expr, _) = parsed("(a OR b) AND c");
+
+        assert_eq!(Expr::And(Box::new(Expr::Or(pattern(0), pattern(1))), pattern(2)), expr);
+    }
+
+    #[test]
+    fn quoted_words_and_reused_patterns() {
+        let (expr, patterns) = parsed("\"out of memory\" OR (OOM AND NOT \"out of memory\")");
+
+        assert_eq!(vec!["out of memory", "OOM"], patterns);
+        assert!(expr.eval(&[true, false]));
+        assert!(expr.eval(&[false, true]));
+        assert!(!expr.eval(&[false, false]));
+    }
+
+    #[test]
+    fn lowercase_operators_are_words() {
+        let (_, patterns) = parsed("\"rock and roll\" OR and");
+
+        assert_eq!(vec!["rock and roll", "and"], patterns);
+    }
+
+    #[test]
+    fn positive_patterns() {
+        let (expr, _) = parsed("a AND NOT (b OR NOT c)");
+        let mut positive = vec![false; 3];
+        expr.mark_positive(false, &mut positive);
+
+        // c is behind two NOTs, so finding it helps the line to match.
+        assert_eq!(vec![true, false, true], positive);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!("expected AND or OR between the words, quote the text if it has spaces", error("out of memory"));
+        assert_eq!("a '(' is missing its closing ')'", error("(a OR b"));
+        assert_eq!("')' without a '(' before it", error("a OR b)"));
+        assert_eq!("AND and OR need a word on both sides", error("a AND OR b"));
+        assert_eq!("the expression ended too early", error("a AND"));
+        assert_eq!("a quote is missing its closing '\"'", error("\"out of"));
+    }
+}
@@ -0,0 +1,96 @@
+use unicase::UniCase;
+
+// How -i decides that two pieces of text are the same.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CaseFold {
+    // Unicode case folding (the full mappings of CaseFolding.txt), so "ß" matches "SS" and "ς" matches "Σ".
+    // It doesn't depend on the language, which means Turkish "ı" and "I" are still different letters.
+    Unicode,
+    // Just char::to_lowercase, which is how minigrep compared text before.
+    Lowercase,
+}
+
+// Folds the text and remembers where every byte of the folded text came from.
+// Folding can change the length of a character ("ß" becomes "ss"), so without the positions
+// a match in the folded text couldn't be pointed at in the original. The extra position at the end is the length of the text.
+pub fn fold_with_positions(text: &str, mode: CaseFold) -> (String, Vec<usize>) {
+    let mut folded = String::with_capacity(text.len());
+    let mut positions = Vec::with_capacity(text.len() + 1);
+
+    for (position, c) in text.char_indices() {
+        let before = folded.len();
+        fold_char(c, mode, &mut folded);
+        positions.extend(std::iter::repeat_n(position, folded.len() - before));
+    }
+    positions.push(text.len());
+
+    (folded, positions)
+}
+
+pub fn fold(text: &str, mode: CaseFold) -> String {
+    let mut folded = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        fold_char(c, mode, &mut folded);
+    }
+
+    folded
+}
+
+fn fold_char(c: char, mode: CaseFold, folded: &mut String) {
+    match mode {
+        // ASCII is most of the text in practice, and it doesn't need the table.
+        CaseFold::Unicode if c.is_ascii() => folded.push(c.to_ascii_lowercase()),
+        // Case folding works one character at a time, so folding a single character is the same as folding it in a string.
+        CaseFold::Unicode => folded.push_str(&UniCase::unicode(&*c.encode_utf8(&mut [0; 4])).to_folded_case()),
+        CaseFold::Lowercase => folded.extend(c.to_lowercase()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sharp_s() {
+        assert_eq!("strasse", fold("Straße", CaseFold::Unicode));
+        assert_eq!("strasse", fold("STRASSE", CaseFold::Unicode));
+        assert_eq!("ss", fold("ẞ", CaseFold::Unicode));
+        // Lowercase leaves ß alone, so it never matches "ss".
+        assert_eq!("straße", fold("Straße", CaseFold::Lowercase));
+    }
+
+    #[test]
+    fn greek_final_sigma() {
+        // Σ, σ and the final ς are all the same letter after folding.
+        assert_eq!(fold("ΟΔΟΣ", CaseFold::Unicode), fold("οδος", CaseFold::Unicode));
+        assert_eq!(fold("οδοσ", CaseFold::Unicode), fold("οδος", CaseFold::Unicode));
+        assert_ne!(fold("οδοσ", CaseFold::Lowercase), fold("οδος", CaseFold::Lowercase));
+    }
+
+    #[test]
+    fn turkish_i_is_locale_neutral() {
+        // Without a language the dotted capital İ folds to "i" with a combining dot,
+        // and dotless ı doesn't fold into i, so "DIŞ" is "diş" and not "dış".
+        assert_eq!("i\u{307}stanbul", fold("İstanbul", CaseFold::Unicode));
+        assert_eq!("diş", fold("DIŞ", CaseFold::Unicode));
+        assert_eq!("dış", fold("dış", CaseFold::Unicode));
+    }
+
+    #[test]
+    fn compatibility_characters() {
+        // The micro sign and the Kelvin sign look like letters and fold into them.
+        assert_eq!(fold("μm", CaseFold::Unicode), fold("µm", CaseFold::Unicode));
+        assert_eq!("k", fold("\u{212A}", CaseFold::Unicode));
+        assert_eq!("µm", fold("µm", CaseFold::Lowercase));
+    }
+
+    #[test]
+    fn positions_point_into_the_original() {
+        let (folded, positions) = fold_with_positions("aßb", CaseFold::Unicode);
+
+        assert_eq!("assb", folded);
+        // "ß" is two bytes in the original and "ss" is two bytes in the folded text, both start at 1.
+        assert_eq!(vec![0, 1, 1, 3, 4], positions);
+    }
+}
@@ -0,0 +1,108 @@
+use std::ops::Range;
+
+// Finds the part of the text that is the closest to the query, counted in Levenshtein distance
+// (how many characters have to be added, removed or changed to turn one into the other).
+// Returns the distance and the byte range of the closest part, or None if even that is further than max_distance.
+// When there are many equally close parts, the first one wins, and it's made as long as it stays as close.
+//
+// This is the usual edit distance table with one change: the match can start anywhere in the text for free,
+// so the first row is all zeros instead of 0, 1, 2... Only one column of the table is kept at a time,
+// next to the byte where the part of the text of each cell starts.
+pub fn find(query: &[char], text: &str, max_distance: usize) -> Option<(usize, Range<usize>)> {
+    // Before any text has been read the query can only be matched by deleting all of it.
+    let mut cost: Vec<usize> = (0..=query.len()).collect();
+    let mut start = vec![0; query.len() + 1];
+    let mut best = (query.len(), 0..0);
+
+    for (position, c) in text.char_indices() {
+        let end = position + c.len_utf8();
+        // The cell up and to the left, from the column of the previous character.
+        let mut diagonal = (cost[0], start[0]);
+
+        // An empty query matches the empty text right after this character.
+        cost[0] = 0;
+        start[0] = end;
+
+        for i in 1..=query.len() {
+            let changed = (diagonal.0 + usize::from(query[i - 1] != c), diagonal.1);
+            let text_removed = (cost[i] + 1, start[i]);
+            let query_removed = (cost[i - 1] + 1, start[i - 1]);
+            diagonal = (cost[i], start[i]);
+
+            // min_by_key keeps the first of equal costs, so a changed character is preferred over removing one.
+            (cost[i], start[i]) = [changed, text_removed, query_removed].into_iter().min_by_key(|cell| cell.0).unwrap();
+        }
+
+        let (distance, from) = (cost[query.len()], start[query.len()]);
+        // "strase" is as close to "strass" as it is to "strasse", the longer one is the whole word.
+        if distance < best.0 || (distance == best.0 && from == best.1.start && best.1.end == position) {
+            best = (distance, from..end);
+
+            // Nothing can be closer than an exact match.
+            if best.0 == 0 {
+                break;
+            }
+        }
+    }
+
+    if best.0 <= max_distance {
+        Some(best)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_str(query: &str, text: &str, max_distance: usize) -> Option<(usize, Range<usize>)> {
+        find(&query.chars().collect::<Vec<_>>(), text, max_distance)
+    }
+
+    #[test]
+    fn exact_match() {
+        assert_eq!(Some((0, 6..10)), find_str("fast", "safe, fast, productive.", 0));
+        assert_eq!(None, find_str("slow", "safe, fast, productive.", 0));
+    }
+
+    #[test]
+    fn changed_character() {
+        assert_eq!(Some((1, 6..10)), find_str("fest", "safe, fast, productive.", 1));
+        assert_eq!(None, find_str("fest", "safe, fast, productive.", 0));
+    }
+
+    #[test]
+    fn missing_and_extra_characters() {
+        // "conection" is missing an "n", "connnection" has one too many.
+        assert_eq!(Some((1, 10..20)), find_str("conection", "database: connection refused", 1));
+        assert_eq!(Some((1, 10..20)), find_str("connnection", "database: connection refused", 1));
+    }
+
+    #[test]
+    fn swapped_characters_are_two_edits() {
+        assert_eq!(None, find_str("recieve", "failed to receive", 1));
+        assert_eq!(Some((2, 10..17)), find_str("recieve", "failed to receive", 2));
+    }
+
+    #[test]
+    fn closest_part_wins() {
+        // "tast" is only one edit away, but "test" is right there too.
+        assert_eq!(Some((0, 10..14)), find_str("test", "fast tast test", 2));
+        // With equal distances the first one is used.
+        assert_eq!(Some((1, 0..3)), find_str("cat", "bat cot", 1));
+    }
+
+    #[test]
+    fn ranges_are_bytes_of_whole_characters() {
+        assert_eq!(Some((1, 3..10)), find_str("strase", "in straße", 1));
+    }
+
+    #[test]
+    fn short_text() {
+        // Everything has to be added, which is as many edits as the query is long.
+        assert_eq!(Some((3, 0..0)), find_str("abc", "", 3));
+        assert_eq!(Some((1, 0..2)), find_str("abc", "ab", 1));
+        assert_eq!(None, find_str("abc", "", 2));
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::fold::{self, CaseFold};
+use crate::{input, walk, Config};
+
+// The index lives at the top of the indexed directory. The dot hides it from the search itself.
+pub const INDEX_FILE: &str = ".minigrep-index";
+
+// The file starts with this, the number at the end goes up when the layout changes.
+const MAGIC: &[u8; 8] = b"MGINDEX1";
+
+// A trigram index remembers which files have which three byte sequences in them. A file can only
+// contain "needle" if it has "nee", "eed", "edl" and "dle", so most files can be ruled out without reading them.
+// The text is case folded first, the same folding -i uses, so the same index works with and without -i.
+//
+// On the disk, every number little endian:
+//   MAGIC
+//   u64 seconds and u32 nanoseconds of when the build started
+//   u32 number of files, then for every file:
+//       u32 length of the path, the path relative to the index ('/' between the parts),
+//       u64 seconds and u32 nanoseconds of the modification time, u64 size, u64 hash of the text
+//   u32 number of trigrams, then for every trigram:
+//       u32 the trigram, u32 number of files, u32 for every file that has it (its place in the list of files)
+struct Index {
+    built: Duration,
+    files: Vec<Entry>,
+    postings: HashMap<u32, Vec<u32>>,
+}
+
+// The modification time and size tell if the file has changed since it was indexed.
+// The hash is for when they can't tell, see Index::unchanged.
+#[derive(Debug, PartialEq, Clone)]
+struct Entry {
+    path: String,
+    modified: Duration,
+    size: u64,
+    hash: u64,
+}
+
+// A file modified this close to the start of the build could have been changed again after it was read, without
+// the modification time changing. Some file systems only keep whole seconds, or even less.
+const RACY: Duration = Duration::from_secs(1);
+
+// What index build did, main prints this.
+#[derive(Debug, PartialEq)]
+pub struct IndexStats {
+    pub path: PathBuf,
+    pub files: usize,
+    // Files that were new or had changed. The rest were taken from the old index as they were.
+    pub read: usize,
+}
+
+// Indexes every file a search of the directory would find (ignore files and hidden files are skipped the same way).
+// An index that is already there is updated: only files with a different modification time or size are read again,
+// and files that don't exist anymore are dropped.
+pub fn build(dir: &Path) -> Result<IndexStats, Box<dyn Error>> {
+    let index_path = dir.join(INDEX_FILE);
+    // Taken before any file is read, a file changed while the build runs is then newer than this.
+    let built = since_epoch(SystemTime::now());
+    // A missing or broken index is just built again from the start.
+    let old = read(&index_path).ok();
+    let old_trigrams = old.as_ref().map(Index::trigrams_by_path).unwrap_or_default();
+
+    let mut files = Vec::new();
+    let mut postings: HashMap<u32, Vec<u32>> = HashMap::new();
+    let mut read_files = 0;
+
+    // Directories that can't be read are left out like files that can't be read.
+    for file in walk::files(dir, &walk::Filter::build(&[], &[])?).files {
+        let Some(path) = relative_key(dir, &file) else {
+            continue;
+        };
+
+        let (entry, trigrams) = match (&old, old_trigrams.get(path.as_str())) {
+            (Some(old), Some((entry, trigrams))) if old.unchanged(entry, &file) => (entry.clone(), trigrams.clone()),
+            _ => match index_file(&file, path) {
+                Ok(indexed) => {
+                    read_files += 1;
+                    indexed
+                }
+                // A file that can't be read isn't indexed, the searches will then always read it themselves.
+                Err(_) => continue,
+            },
+        };
+
+        let number = files.len() as u32;
+        for trigram in trigrams {
+            postings.entry(trigram).or_default().push(number);
+        }
+        files.push(entry);
+    }
+
+    let index = Index { built, files, postings };
+    write(&index, &index_path)?;
+
+    Ok(IndexStats { path: index_path, files: index.files.len(), read: read_files })
+}
+
+// Leaves out the files that can't match. The index of the directory, or of the closest parent directory that has one, is used.
+// Files the index doesn't know about or that changed after it was built are always kept, so the results are the same
+// as without --index, just faster. Searches the index can't narrow down (see required) keep every file.
+pub fn candidates(config: &Config, dir: &Path, files: Vec<PathBuf>) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let Some(required) = required(config) else {
+        return Ok(files);
+    };
+
+    let canonical = fs::canonicalize(dir)?;
+    let root = canonical
+        .ancestors()
+        .find(|ancestor| ancestor.join(INDEX_FILE).is_file())
+        .ok_or_else(|| format!("no index found for '{}', run 'minigrep index build' first", dir.display()))?;
+    let index = read(&root.join(INDEX_FILE)).map_err(|e| format!("can't read the index of '{}': {e}", root.display()))?;
+    // Where the searched directory is inside the indexed one, the paths in the index start with it.
+    let prefix = canonical.strip_prefix(root)?.to_path_buf();
+
+    let numbers: HashMap<&str, usize> = index.files.iter().enumerate().map(|(number, entry)| (entry.path.as_str(), number)).collect();
+    let mut matching = vec![false; index.files.len()];
+    for trigrams in &required {
+        for (number, matches) in index.files_with_all(trigrams).into_iter().enumerate() {
+            matching[number] |= matches;
+        }
+    }
+
+    Ok(files
+        .into_iter()
+        .filter(|file| {
+            let known = file
+                .strip_prefix(dir)
+                .ok()
+                .and_then(|relative| relative_key(Path::new(""), &prefix.join(relative)))
+                .and_then(|key| numbers.get(key.as_str()).copied());
+
+            match known {
+                Some(number) if index.unchanged(&index.files[number], file) => matching[number],
+                // Changed since the index was built, the index can't tell anything about it.
+                _ => true,
+            }
+        })
+        .collect())
+}
+
+// The trigrams a file has to have for the search to match in it, one list for every pattern (a file needs all the
+// trigrams of any one of them). None means the index can't help and every file has to be searched:
+// a pattern shorter than three bytes, regular expressions, fuzzy matching and expressions can match without any
+// fixed text, -v and -L are about the lines and files that don't match, and with -z and --encoding the searched
+// text isn't the text that was indexed. -i with the lowercase mode doesn't fold the same way the index does.
+fn required(config: &Config) -> Option<Vec<Vec<u32>>> {
+    if config.regex
+        || config.fuzzy.is_some()
+        || config.expression
+        || config.invert
+        || config.files_without_match
+        || config.search_zip
+        || config.encoding.is_some()
+        || (config.ignore_case && config.case_fold == CaseFold::Lowercase)
+    {
+        return None;
+    }
+
+    config
+        .patterns
+        .iter()
+        .map(|pattern| {
+            let trigrams = trigrams(pattern);
+            // Folding is done one character at a time, so a file that has the pattern has its folded trigrams too,
+            // whether or not -i is used.
+            if trigrams.is_empty() {
+                None
+            } else {
+                Some(trigrams)
+            }
+        })
+        .collect()
+}
+
+// The trigrams of the folded text, sorted and without repeats.
+fn trigrams(text: &str) -> Vec<u32> {
+    let folded = fold::fold(text, CaseFold::Unicode);
+    let mut trigrams: Vec<u32> = folded.as_bytes().windows(3).map(|bytes| u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]])).collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+// Invalid UTF-8 becomes U+FFFD like it does when a binary file is searched.
+fn index_file(file: &Path, path: String) -> io::Result<(Entry, Vec<u32>)> {
+    // The modification time is taken before reading, so a change while reading makes the file look changed later.
+    let (modified, size) = stat(file)?;
+    let text = read_text(file)?;
+    let entry = Entry { path, modified, size, hash: hash(&text) };
+    Ok((entry, trigrams(&String::from_utf8_lossy(&text))))
+}
+
+// The file is read the same way a search without -z and --encoding reads it, so a UTF-16 file is indexed as its text.
+fn read_text(file: &Path) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    input::open(file, false, None)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn stat(file: &Path) -> io::Result<(Duration, u64)> {
+    let metadata = fs::metadata(file)?;
+    Ok((since_epoch(metadata.modified()?), metadata.len()))
+}
+
+// A time before 1970 is possible, such a file is just always seen as changed.
+fn since_epoch(time: SystemTime) -> Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+// FNV-1a. The hasher of the standard library can change between Rust versions, this has to stay the same
+// for as long as the index file is around.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+// The path of the file relative to the directory, with '/' between the parts on every system.
+fn relative_key(dir: &Path, file: &Path) -> Option<String> {
+    let relative = file.strip_prefix(dir).ok()?;
+    let parts: Option<Vec<&str>> = relative
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .map(|component| match component {
+            Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect();
+    Some(parts?.join("/"))
+}
+
+impl Index {
+    // Whether the file is still the way it was when it was indexed. Usually the modification time and size are enough,
+    // only the files modified right around the build are read again to compare their text.
+    fn unchanged(&self, entry: &Entry, file: &Path) -> bool {
+        match stat(file) {
+            Ok((modified, size)) if modified == entry.modified && size == entry.size => {
+                modified + RACY < self.built || read_text(file).is_ok_and(|text| hash(&text) == entry.hash)
+            }
+            _ => false,
+        }
+    }
+
+    fn trigrams_by_path(&self) -> HashMap<&str, (Entry, Vec<u32>)> {
+        let mut by_file: Vec<Vec<u32>> = vec![Vec::new(); self.files.len()];
+        for (trigram, files) in &self.postings {
+            for &number in files {
+                by_file[number as usize].push(*trigram);
+            }
+        }
+
+        self.files.iter().zip(by_file).map(|(entry, trigrams)| (entry.path.as_str(), (entry.clone(), trigrams))).collect()
+    }
+
+    // For every file, whether it has all the trigrams.
+    fn files_with_all(&self, trigrams: &[u32]) -> Vec<bool> {
+        let mut matching = vec![true; self.files.len()];
+
+        for trigram in trigrams {
+            let mut has = vec![false; self.files.len()];
+            for &number in self.postings.get(trigram).map(Vec::as_slice).unwrap_or_default() {
+                has[number as usize] = true;
+            }
+            for (matches, has) in matching.iter_mut().zip(has) {
+                *matches &= has;
+            }
+        }
+
+        matching
+    }
+}
+
+fn read(path: &Path) -> io::Result<Index> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a minigrep index, or made by another version"));
+    }
+
+    let built = read_time(&mut reader)?;
+    let mut files = Vec::new();
+    for _ in 0..read_u32(&mut reader)? {
+        let mut path = vec![0; read_u32(&mut reader)? as usize];
+        reader.read_exact(&mut path)?;
+        let path = String::from_utf8(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let modified = read_time(&mut reader)?;
+        let size = read_u64(&mut reader)?;
+        let hash = read_u64(&mut reader)?;
+        files.push(Entry { path, modified, size, hash });
+    }
+
+    let mut postings = HashMap::new();
+    for _ in 0..read_u32(&mut reader)? {
+        let trigram = read_u32(&mut reader)?;
+        let mut numbers = Vec::new();
+        for _ in 0..read_u32(&mut reader)? {
+            let number = read_u32(&mut reader)?;
+            if number as usize >= files.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "the index is broken"));
+            }
+            numbers.push(number);
+        }
+        postings.insert(trigram, numbers);
+    }
+
+    Ok(Index { built, files, postings })
+}
+
+// Written to a temporary file first and renamed over the old index like --in-place does,
+// so a search running at the same time never sees half an index.
+fn write(index: &Index, path: &Path) -> io::Result<()> {
+    let temp = path.with_file_name(format!("{INDEX_FILE}.{}.tmp", std::process::id()));
+    let result = write_to(index, &temp).and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+fn write_to(index: &Index, temp: &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(OpenOptions::new().write(true).create_new(true).open(temp)?);
+
+    writer.write_all(MAGIC)?;
+    write_time(&mut writer, index.built)?;
+    writer.write_all(&(index.files.len() as u32).to_le_bytes())?;
+    for entry in &index.files {
+        writer.write_all(&(entry.path.len() as u32).to_le_bytes())?;
+        writer.write_all(entry.path.as_bytes())?;
+        write_time(&mut writer, entry.modified)?;
+        writer.write_all(&entry.size.to_le_bytes())?;
+        writer.write_all(&entry.hash.to_le_bytes())?;
+    }
+
+    // Sorted, so the same files always give the same index.
+    let mut postings: Vec<_> = index.postings.iter().collect();
+    postings.sort_unstable_by_key(|(trigram, _)| **trigram);
+    writer.write_all(&(postings.len() as u32).to_le_bytes())?;
+    for (trigram, numbers) in postings {
+        writer.write_all(&trigram.to_le_bytes())?;
+        writer.write_all(&(numbers.len() as u32).to_le_bytes())?;
+        for number in numbers {
+            writer.write_all(&number.to_le_bytes())?;
+        }
+    }
+
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+}
+
+fn write_time(writer: &mut impl Write, time: Duration) -> io::Result<()> {
+    writer.write_all(&time.as_secs().to_le_bytes())?;
+    writer.write_all(&time.subsec_nanos().to_le_bytes())
+}
+
+fn read_time(reader: &mut impl Read) -> io::Result<Duration> {
+    let seconds = read_u64(reader)?;
+    let nanos = read_u32(reader)?;
+    Ok(Duration::new(seconds, nanos))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn corpus(name: &str) -> TempDir {
+        let root = TempDir::new(&format!("index_{name}"));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("poem.txt"), "I'm nobody! Who are you?\nAre you nobody, too?").unwrap();
+        fs::write(root.join("rust.txt"), "Rust:\nsafe, fast, productive.\nPick three.").unwrap();
+        fs::write(root.join("docs/street.txt"), "Straße").unwrap();
+        root
+    }
+
+    fn config(args: &[&str]) -> Config {
+        let args: Vec<String> = ["minigrep"].iter().chain(args).map(|arg| arg.to_string()).collect();
+        Config::build_layered(&args, Vec::new()).unwrap()
+    }
+
+    fn found(root: &Path, dir: &Path, args: &[&str]) -> Vec<String> {
+        let config = config(args);
+        let files = walk::files(dir, &walk::Filter::build(&[], &[]).unwrap()).files;
+        candidates(&config, dir, files)
+            .unwrap()
+            .iter()
+            .map(|file| relative_key(root, file).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn narrows_down_the_files() {
+        let root = corpus("narrow");
+        assert_eq!(IndexStats { path: root.join(INDEX_FILE), files: 3, read: 3 }, build(&root).unwrap());
+
+        assert_eq!(vec!["poem.txt"], found(&root, &root, &["nobody"]));
+        assert_eq!(vec!["poem.txt", "rust.txt"], found(&root, &root, &["-e", "nobody", "-e", "three"]));
+        assert!(found(&root, &root, &["somebody"]).is_empty());
+        // "ss" is what ß folds to, with or without -i.
+        assert_eq!(vec!["docs/street.txt"], found(&root, &root, &["-i", "STRASSE"]));
+        // A subdirectory uses the index of its parent.
+        assert_eq!(vec!["docs/street.txt"], found(&root, &root.join("docs"), &["Straße"]));
+    }
+
+    #[test]
+    fn searches_it_cant_narrow_keep_every_file() {
+        let root = corpus("every");
+        build(&root).unwrap();
+
+        assert_eq!(3, found(&root, &root, &["--regex", "no+body"]).len());
+        assert_eq!(3, found(&root, &root, &["-v", "nobody"]).len());
+        assert_eq!(3, found(&root, &root, &["no"]).len());
+    }
+
+    #[test]
+    fn changed_files_are_searched_and_updated() {
+        let root = corpus("changed");
+        build(&root).unwrap();
+
+        // The new text isn't in the index yet, but the file changed, so it's searched anyway. It's the same size
+        // and probably has the same modification time, it was written in the same second as the index.
+        fs::write(root.join("rust.txt"), "Rust:\nsafe, fast, productive.\nnobody, yes").unwrap();
+        fs::write(root.join("new.txt"), "nobody").unwrap();
+        assert_eq!(vec!["new.txt", "poem.txt", "rust.txt"], found(&root, &root, &["nobody"]));
+
+        // Only the changed and the new file are read again, the deleted one is dropped.
+        fs::remove_file(root.join("docs/street.txt")).unwrap();
+        assert_eq!(IndexStats { path: root.join(INDEX_FILE), files: 3, read: 2 }, build(&root).unwrap());
+        assert_eq!(vec!["new.txt", "poem.txt", "rust.txt"], found(&root, &root, &["nobody"]));
+        assert_eq!(vec!["poem.txt"], found(&root, &root, &["too"]));
+    }
+
+    #[test]
+    fn missing_or_broken_index() {
+        let root = corpus("missing");
+        let files = vec![root.join("poem.txt")];
+        // The temp directory could be inside a directory with an index, so the error is only checked when it isn't.
+        if !root.ancestors().any(|dir| dir.join(INDEX_FILE).exists()) {
+            assert!(candidates(&config(&["nobody"]), &root, files.clone()).is_err());
+        }
+
+        fs::write(root.join(INDEX_FILE), "not an index").unwrap();
+        assert!(candidates(&config(&["nobody"]), &root, files).is_err());
+        // Building replaces the broken index.
+        assert_eq!(3, build(&root).unwrap().files);
+    }
+}
@@ -0,0 +1,281 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use bzip2::bufread::MultiBzDecoder;
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use flate2::bufread::MultiGzDecoder;
+use xz2::bufread::XzDecoder;
+
+use crate::Line;
+
+// The path that means "read from stdin", and the name stdin gets in the output like grep does it.
+pub const STDIN: &str = "-";
+pub const STDIN_NAME: &str = "(standard input)";
+
+// The first bytes of every compressed format -z understands, the file name isn't trusted.
+const GZIP: &[u8] = &[0x1f, 0x8b];
+const BZIP2: &[u8] = b"BZh";
+const XZ: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const ZSTD: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+// Opens a file or stdin for reading. Both are buffered, so lines can be read without loading the whole input.
+// With decompress compressed input is decompressed while it's read, anything else is read as it is.
+// After that the text is decoded to UTF-8, see decoded.
+pub fn open(path: &Path, decompress: bool, encoding: Option<&'static Encoding>) -> io::Result<Box<dyn BufRead>> {
+    let reader: Box<dyn BufRead> = if path.as_os_str() == STDIN {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(path)?))
+    };
+
+    let reader = if decompress { decompressed(reader)? } else { reader };
+
+    Ok(decoded(reader, encoding))
+}
+
+// Turns the text into UTF-8, which is all the rest of minigrep understands. Text that starts with a BOM
+// is decoded the way the BOM says, so UTF-16 files from Windows just work. Otherwise the encoding from
+// --encoding is used, and without one the bytes are passed on as they are and expected to be UTF-8 already.
+// Bytes that aren't valid in the encoding become U+FFFD, only UTF-8 that is passed on can still be invalid.
+// Byte offsets (-b) are counted in the decoded text.
+pub fn decoded(reader: Box<dyn BufRead>, encoding: Option<&'static Encoding>) -> Box<dyn BufRead> {
+    let decoder = DecodeReaderBytesBuilder::new().encoding(encoding).bom_override(true).build(reader);
+
+    Box::new(BufReader::new(decoder))
+}
+
+// Looks at the first bytes to see if the input is compressed. The decoders read the rest of the input
+// a piece at a time, so a big archive is never decompressed into memory as a whole.
+// The multi decoders keep going after the first stream, "cat a.gz b.gz > c.gz" is a valid gzip file too.
+pub fn decompressed(mut reader: Box<dyn BufRead>) -> io::Result<Box<dyn BufRead>> {
+    let start = reader.fill_buf()?;
+
+    let decoder: Box<dyn Read> = if start.starts_with(GZIP) {
+        Box::new(MultiGzDecoder::new(reader))
+    } else if start.starts_with(BZIP2) {
+        Box::new(MultiBzDecoder::new(reader))
+    } else if start.starts_with(XZ) {
+        Box::new(XzDecoder::new_multi_decoder(reader))
+    } else if start.starts_with(ZSTD) {
+        Box::new(zstd::stream::read::Decoder::with_buffer(reader)?)
+    } else {
+        return Ok(reader);
+    };
+
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+// Input with a NUL byte near the start is treated as binary, the same heuristic grep uses.
+// Only the already buffered bytes are looked at, so nothing is consumed from the reader.
+pub fn is_binary(reader: &mut dyn BufRead) -> io::Result<bool> {
+    Ok(reader.fill_buf()?.contains(&0))
+}
+
+// A line read from the input. The reader reuses nothing between lines, so the line owns its text.
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedLine {
+    pub index: usize,
+    pub offset: usize,
+    pub text: String,
+}
+
+impl OwnedLine {
+    pub fn as_line(&self) -> Line<'_> {
+        Line { index: self.index, offset: self.offset, text: &self.text }
+    }
+}
+
+// Reads the input one line at a time, so memory use depends on the longest line instead of the size of the input.
+pub struct ReadLines<R> {
+    reader: R,
+    index: usize,
+    offset: usize,
+    // Invalid UTF-8 is an error, unless the reader is lossy.
+    lossy: bool,
+    found_invalid: bool,
+}
+
+pub fn read_lines<R: BufRead>(reader: R) -> ReadLines<R> {
+    ReadLines { reader, index: 0, offset: 0, lossy: false, found_invalid: false }
+}
+
+impl<R> ReadLines<R> {
+    // Lines that aren't valid UTF-8 get U+FFFD in place of the invalid bytes instead of being an error.
+    pub fn lossy(mut self) -> ReadLines<R> {
+        self.lossy = true;
+        self
+    }
+
+    // True once a lossy reader has replaced something, the input wasn't really UTF-8 text then.
+    pub fn found_invalid(&self) -> bool {
+        self.found_invalid
+    }
+}
+
+impl<R: BufRead> Iterator for ReadLines<R> {
+    type Item = io::Result<OwnedLine>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut raw = Vec::new();
+
+        let read = match self.reader.read_until(b'\n', &mut raw) {
+            Ok(0) => return None,
+            Ok(read) => read,
+            Err(e) => return Some(Err(e)),
+        };
+
+        // Same line endings as str::lines, "\r\n" is stripped the same way as "\n".
+        if raw.ends_with(b"\n") {
+            raw.pop();
+            if raw.ends_with(b"\r") {
+                raw.pop();
+            }
+        }
+
+        let text = match String::from_utf8(raw) {
+            Ok(text) => text,
+            Err(e) if self.lossy => {
+                self.found_invalid = true;
+                String::from_utf8_lossy(e.as_bytes()).into_owned()
+            }
+            Err(e) => return Some(Err(io::Error::new(io::ErrorKind::InvalidData, e))),
+        };
+        let line = OwnedLine { index: self.index, offset: self.offset, text };
+
+        self.index += 1;
+        self.offset += read;
+
+        Some(Ok(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_lines_with_offsets() {
+        let input = "Rust:\r\nsafe, fast, productive.\nPick three.".as_bytes();
+        let lines: Vec<OwnedLine> = read_lines(input).map(Result::unwrap).collect();
+
+        assert_eq!(
+            vec![
+                OwnedLine { index: 0, offset: 0, text: "Rust:".to_string() },
+                OwnedLine { index: 1, offset: 7, text: "safe, fast, productive.".to_string() },
+                OwnedLine { index: 2, offset: 31, text: "Pick three.".to_string() },
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn reads_the_same_lines_as_str_lines() {
+        let contents = "one\n\ntwo\r\n\nthree\n";
+        let lines: Vec<String> = read_lines(contents.as_bytes()).map(|line| line.unwrap().text).collect();
+
+        assert_eq!(contents.lines().collect::<Vec<_>>(), lines);
+    }
+
+    #[test]
+    fn invalid_utf8_is_an_error() {
+        let mut lines = read_lines(&b"fine\n\xff\xfe\n"[..]);
+
+        assert!(lines.next().unwrap().is_ok());
+        assert_eq!(io::ErrorKind::InvalidData, lines.next().unwrap().unwrap_err().kind());
+    }
+
+    #[test]
+    fn lossy_lines() {
+        let mut lines = read_lines(&b"fine\ncaf\xe9\n"[..]).lossy();
+
+        assert_eq!("fine", lines.next().unwrap().unwrap().text);
+        assert!(!lines.found_invalid());
+        assert_eq!("caf\u{fffd}", lines.next().unwrap().unwrap().text);
+        assert!(lines.found_invalid());
+    }
+
+    fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> String {
+        let mut text = String::new();
+        decoded(Box::new(io::Cursor::new(bytes.to_vec())), encoding).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn decodes_utf16_with_a_bom() {
+        let little: Vec<u8> = [0xff, 0xfe].into_iter().chain("Pick three.".encode_utf16().flat_map(u16::to_le_bytes)).collect();
+        let big: Vec<u8> = [0xfe, 0xff].into_iter().chain("Pick three.".encode_utf16().flat_map(u16::to_be_bytes)).collect();
+
+        assert_eq!("Pick three.", decode(&little, None));
+        assert_eq!("Pick three.", decode(&big, None));
+        // The BOM is more reliable than a guess made for every file.
+        assert_eq!("Pick three.", decode(&little, Encoding::for_label(b"shift_jis")));
+    }
+
+    #[test]
+    fn decodes_the_given_encoding() {
+        assert_eq!("café", decode(b"caf\xe9", Encoding::for_label(b"latin1")));
+        assert_eq!("“quoted”", decode(b"\x93quoted\x94", Encoding::for_label(b"windows-1252")));
+        assert_eq!("日本", decode(b"\x93\xfa\x96\x7b", Encoding::for_label(b"shift_jis")));
+        // Without an encoding the bytes are left alone, invalid UTF-8 included.
+        let mut raw = Vec::new();
+        decoded(Box::new(&b"caf\xe9"[..]), None).read_to_end(&mut raw).unwrap();
+        assert_eq!(b"caf\xe9", &raw[..]);
+    }
+
+    #[test]
+    fn decompresses_every_format() {
+        use std::io::Write;
+
+        let text = "I'm nobody! Who are you?\nAre you nobody, too?\n";
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(text.as_bytes()).unwrap();
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2.write_all(text.as_bytes()).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(text.as_bytes()).unwrap();
+
+        let compressed = vec![
+            gzip.finish().unwrap(),
+            bzip2.finish().unwrap(),
+            xz.finish().unwrap(),
+            zstd::encode_all(text.as_bytes(), 0).unwrap(),
+            // Text that isn't compressed is read as it is.
+            text.as_bytes().to_vec(),
+        ];
+
+        for bytes in compressed {
+            let mut read = String::new();
+            decompressed(Box::new(io::Cursor::new(bytes))).unwrap().read_to_string(&mut read).unwrap();
+            assert_eq!(text, read);
+        }
+    }
+
+    #[test]
+    fn concatenated_gzip_files() {
+        use std::io::Write;
+
+        let mut compressed = Vec::new();
+        for part in ["one\n", "two\n"] {
+            let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            gzip.write_all(part.as_bytes()).unwrap();
+            compressed.extend(gzip.finish().unwrap());
+        }
+
+        let lines: Vec<String> = read_lines(decompressed(Box::new(io::Cursor::new(compressed))).unwrap())
+            .map(|line| line.unwrap().text)
+            .collect();
+        assert_eq!(vec!["one", "two"], lines);
+    }
+
+    #[test]
+    fn binary_detection() {
+        let mut binary = &[0x89, b'P', b'N', b'G', 0, 0, 1][..];
+        let mut text = "readme".as_bytes();
+
+        assert!(is_binary(&mut binary).unwrap());
+        assert!(!is_binary(&mut text).unwrap());
+    }
+}
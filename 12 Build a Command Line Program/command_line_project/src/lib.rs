@@ -1,89 +1,976 @@
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
-use std::fs;
+use std::fmt;
 use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
 
+use encoding_rs::Encoding;
+use regex::Regex;
+use serde::Serialize;
+
+pub mod args;
+mod edit;
+mod expr;
+mod fold;
+mod fuzzy;
+mod index;
+mod input;
+mod matcher;
+mod output;
+mod pool;
+mod scan;
+mod settings;
+mod tui;
+mod walk;
+
+#[cfg(test)]
+mod testing;
+
+use args::ArgsError;
+pub use fold::CaseFold;
+pub use index::IndexStats;
+use matcher::Matcher;
+pub use settings::{Layer, Source};
+use walk::PathError;
+
+// Clone is for the interactive mode, every search there runs on its own thread with its own copy.
+#[derive(Clone)]
 pub struct Config {
-    pub query: String,
+    // The query, or every -e and line of -f. A line matches when any of them matches.
+    pub patterns: Vec<String>,
+    // With --expr every pattern is a boolean expression of words, like "error AND NOT timeout".
+    pub expression: bool,
+    // "-" means stdin, which is also what is searched when no path is given.
     pub file_path: String,
     pub ignore_case: bool,
+    // How ignore_case compares text. The regex mode has its own (simple) case folding and doesn't use this.
+    pub case_fold: CaseFold,
+    pub regex: bool,
+    // Lines match if they have text within this many edits of the query, see search_fuzzy.
+    pub fuzzy: Option<usize>,
+    // The text the matches are replaced with. It's only printed, unless in_place is on too.
+    pub replace: Option<String>,
+    pub in_place: bool,
+    // The original of an edited file is kept with this suffix added to its name.
+    pub backup: Option<String>,
+    // Shows what in_place would do without changing any files.
+    pub dry_run: bool,
+    pub line_number: bool,
+    pub byte_offset: bool,
+    pub count: bool,
+    // -l and -L only print the file names. Only one of them should be on, -l is used if both are.
+    pub files_with_matches: bool,
+    pub files_without_match: bool,
+    pub invert: bool,
+    // None means there is no limit.
+    pub max_count: Option<usize>,
+    pub output: Output,
+    pub color: Color,
+    // Lines of context printed before and after each match.
+    pub before_context: usize,
+    pub after_context: usize,
+    // How many files are searched at the same time when file_path is a directory.
+    pub jobs: usize,
+    // Globs are only used when file_path is a directory.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    // Compressed files are decompressed before searching, the output still shows the name of the compressed file.
+    pub search_zip: bool,
+    // None means the encoding isn't known: a BOM is looked for, and without one the input has to be UTF-8.
+    pub encoding: Option<&'static Encoding>,
+    // Invalid UTF-8 is replaced with U+FFFD instead of making the input binary.
+    pub lossy: bool,
+    // Hidden files and the files of .gitignore and .ignore are skipped in directories, unless these are set.
+    pub hidden: bool,
+    pub no_ignore: bool,
+    // Directories are narrowed down with the index of "minigrep index build" before they are searched.
+    pub index: bool,
+    // The query is typed into a terminal UI instead, the patterns are only the query it starts with.
+    pub interactive: bool,
+    // --print-config only prints the configuration, nothing is searched.
+    pub print_config: bool,
+    // Where each option got its value, options that aren't in here have their default value.
+    pub sources: HashMap<&'static str, Source>,
+}
+
+// How the results are printed. Json prints one object per line (JSON Lines), which is easy to read from other programs.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Output {
+    Text,
+    Json,
+}
+
+// When the matches are highlighted. Auto only colors the output when it goes to a terminal and NO_COLOR isn't set.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
 }
 
 impl Config {
     // function name changed, so it makes sense when called (Config::new(args) -> create new config using these arguments).
-    pub fn build(args: &[String]) -> Result<Config, &'static str> {
-        if args.len() < 3 {
-            // There is multiple ways of handling errors and result is more user friendly than panicing.
-            // panic!("not enough arguments");
+    // The config files and the environment are read too, the command line wins over both of them.
+    pub fn build(args: &[String]) -> Result<Config, ArgsError> {
+        Config::build_layered(args, settings::load()?)
+    }
+
+    // Same as build, but the layers under the command line are given instead of read, lowest precedence first.
+    pub fn build_layered(args: &[String], layers: Vec<Layer>) -> Result<Config, ArgsError> {
+        // The first argument is the program name, the parser only wants the actual arguments.
+        let parsed = args::parse(args.iter().skip(1).cloned())?;
+        let print_config = last_switch(&parsed.opts, "print-config")?;
+        let interactive = last_switch(&parsed.opts, "interactive")?;
 
-            // Remember Result can be Ok or Err.
-            return Err("not enough arguments");
+        // There is multiple ways of handling errors and result is more user friendly than panicing.
+        // Each missing piece has its own error, so the user knows what was forgotten.
+        let mut positional = parsed.positional.into_iter();
+
+        // Patterns given with -e or -f replace the query, then the first positional argument is already the path.
+        let mut patterns = Vec::new();
+        for opt in &parsed.opts {
+            match (opt.name, &opt.value) {
+                ("regexp", Some(pattern)) => patterns.push(pattern.clone()),
+                ("file", Some(file)) => patterns.extend(read_patterns(file)?),
+                _ => {}
+            }
+        }
+        let given_patterns = parsed.opts.iter().any(|opt| opt.name == "regexp" || opt.name == "file");
+        // Printing the configuration doesn't need a query, there is nothing to search.
+        // The interactive mode has its query typed in, so there the only argument is the path.
+        if !given_patterns && !interactive {
+            match positional.next() {
+                Some(query) => patterns.push(query),
+                None if print_config => {}
+                None => return Err(ArgsError::MissingQuery),
+            }
         }
 
-        let query = args[1].clone();
-        let file_path = args[2].clone();
+        // The keyboard is read from the terminal, so the interactive mode searches the current directory instead of stdin.
+        let file_path = positional.next().unwrap_or_else(|| {
+            if interactive {
+                String::from(".")
+            } else {
+                input::STDIN.to_string()
+            }
+        });
 
-        // Ignore case variable is populated through IGNORE_CASE variable,
-        // which can be passed by giving argument "IGNORE_CASE=1".
-        // Command line argument will be secondary and environment variable will overwrite it.
-        let ignore_case = if env::var("IGNORE_CASE").is_ok() {
-            env::var("IGNORE_CASE").is_ok()
-        } else {
-            args[3] == "true"
-        };
+        if let Some(extra) = positional.next() {
+            return Err(ArgsError::UnexpectedArgument(extra));
+        }
+
+        let mut ignore_case = false;
+        let mut case_fold = CaseFold::Unicode;
+        let mut regex = false;
+        let mut expression = false;
+        let mut fuzzy = None;
+        let mut replace = None;
+        let mut in_place = false;
+        let mut backup = None;
+        let mut dry_run = false;
+        let mut line_number = false;
+        let mut byte_offset = false;
+        let mut count = false;
+        let mut files_with_matches = false;
+        let mut files_without_match = false;
+        let mut invert = false;
+        let mut max_count = None;
+        let mut output = Output::Text;
+        let mut color = Color::Auto;
+        let mut before_context = 0;
+        let mut after_context = 0;
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+        let mut search_zip = false;
+        let mut encoding = None;
+        let mut lossy = false;
+        let mut hidden = false;
+        let mut index = false;
+        let mut no_ignore = false;
+        // One job per CPU by default, the same as most parallel tools.
+        let mut jobs = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let mut sources = HashMap::new();
+
+        // The command line is the last layer, so its options are applied last and overwrite the others.
+        let command_line = Layer { source: Source::CommandLine, opts: parsed.opts };
+        let opts = layers
+            .into_iter()
+            .chain([command_line])
+            .flat_map(|layer| layer.opts.into_iter().map(move |opt| (opt, layer.source.clone())));
+
+        for (opt, source) in opts {
+            // -C sets both kinds of context, so it's the source of both.
+            match opt.name {
+                "context" => {
+                    sources.insert("before-context", source.clone());
+                    sources.insert("after-context", source);
+                }
+                name => {
+                    sources.insert(name, source);
+                }
+            }
+
+            match opt.name {
+                "ignore-case" => ignore_case = switch(&opt)?,
+                "case-fold" => {
+                    case_fold = match opt.value.as_deref() {
+                        Some("unicode") => CaseFold::Unicode,
+                        Some("lowercase") => CaseFold::Lowercase,
+                        value => {
+                            let value = value.unwrap_or_default().to_string();
+                            return Err(ArgsError::InvalidValue(String::from("--case-fold"), value));
+                        }
+                    }
+                }
+                "regex" => regex = switch(&opt)?,
+                "expr" => expression = switch(&opt)?,
+                "fuzzy" => fuzzy = Some(number(&opt)?),
+                "replace" => replace = opt.value,
+                "in-place" => in_place = switch(&opt)?,
+                "backup" => backup = opt.value,
+                "dry-run" => dry_run = switch(&opt)?,
+                "line-number" => line_number = switch(&opt)?,
+                "byte-offset" => byte_offset = switch(&opt)?,
+                "count" => count = switch(&opt)?,
+                "files-with-matches" => files_with_matches = switch(&opt)?,
+                "files-without-match" => files_without_match = switch(&opt)?,
+                "invert-match" => invert = switch(&opt)?,
+                "max-count" => max_count = Some(number(&opt)?),
+                "output" => {
+                    output = match opt.value.as_deref() {
+                        Some("text") => Output::Text,
+                        Some("json") => Output::Json,
+                        value => {
+                            let value = value.unwrap_or_default().to_string();
+                            return Err(ArgsError::InvalidValue(String::from("--output"), value));
+                        }
+                    }
+                }
+                "color" => {
+                    color = match opt.value.as_deref() {
+                        Some("auto") => Color::Auto,
+                        Some("always") => Color::Always,
+                        Some("never") => Color::Never,
+                        value => {
+                            let value = value.unwrap_or_default().to_string();
+                            return Err(ArgsError::InvalidValue(String::from("--color"), value));
+                        }
+                    }
+                }
+                "before-context" => before_context = number(&opt)?,
+                "after-context" => after_context = number(&opt)?,
+                "context" => {
+                    before_context = number(&opt)?;
+                    after_context = before_context;
+                }
+                "jobs" => {
+                    // The thread pool needs at least one thread.
+                    jobs = number(&opt)?;
+                    if jobs == 0 {
+                        return Err(ArgsError::InvalidValue(String::from("--jobs"), String::from("0")));
+                    }
+                }
+                "include" => include.extend(opt.value),
+                "exclude" => exclude.extend(opt.value),
+                "search-zip" => search_zip = switch(&opt)?,
+                "encoding" => {
+                    // The labels are the ones web browsers use, so "latin1" means Windows-1252 like it does on the web.
+                    let value = opt.value.unwrap_or_default();
+                    encoding = match value.as_str() {
+                        "auto" => None,
+                        label => Some(
+                            Encoding::for_label(label.as_bytes())
+                                .ok_or_else(|| ArgsError::InvalidValue(String::from("--encoding"), value.clone()))?,
+                        ),
+                    }
+                }
+                "lossy" => lossy = switch(&opt)?,
+                "hidden" => hidden = switch(&opt)?,
+                "index" => index = switch(&opt)?,
+                "no-ignore" => no_ignore = switch(&opt)?,
+                _ => {}
+            }
+        }
+
+        // A regex is already a pattern, there is no single text to be close to.
+        if regex && fuzzy.is_some() {
+            return Err(ArgsError::Conflicts(String::from("--regex"), String::from("--fuzzy")));
+        }
+        // Fuzzy results are printed closest first, the lines around them wouldn't be next to them anymore.
+        if fuzzy.is_some() && (before_context > 0 || after_context > 0) {
+            return Err(ArgsError::Conflicts(String::from("--fuzzy"), String::from("--context")));
+        }
+
+        // Editing flags that don't do anything on their own are mistakes, so they are reported instead of ignored.
+        if in_place && replace.is_none() {
+            return Err(ArgsError::Requires(String::from("--in-place"), String::from("--replace")));
+        }
+        if backup.is_some() && !in_place {
+            return Err(ArgsError::Requires(String::from("--backup"), String::from("--in-place")));
+        }
+        if dry_run && !in_place {
+            return Err(ArgsError::Requires(String::from("--dry-run"), String::from("--in-place")));
+        }
+        // The edited file would be written back uncompressed.
+        if in_place && search_zip {
+            return Err(ArgsError::Conflicts(String::from("--in-place"), String::from("--search-zip")));
+        }
+        if in_place && interactive {
+            return Err(ArgsError::Conflicts(String::from("--in-place"), String::from("--interactive")));
+        }
+        // Same with other encodings, the file would be written back as UTF-8.
+        if in_place && encoding.is_some() {
+            return Err(ArgsError::Conflicts(String::from("--in-place"), String::from("--encoding")));
+        }
 
         // and because the function returns Result Config needs to be wrapped.
-        Ok(Config { query, file_path, ignore_case })
+        Ok(Config {
+            patterns,
+            expression,
+            file_path,
+            ignore_case,
+            case_fold,
+            regex,
+            fuzzy,
+            replace,
+            in_place,
+            backup,
+            dry_run,
+            line_number,
+            byte_offset,
+            count,
+            files_with_matches,
+            files_without_match,
+            invert,
+            max_count,
+            output,
+            color,
+            before_context,
+            after_context,
+            jobs,
+            include,
+            exclude,
+            search_zip,
+            encoding,
+            lossy,
+            hidden,
+            no_ignore,
+            index,
+            interactive,
+            print_config,
+            sources,
+        })
+    }
+
+    // The effective configuration and where every value came from, for --print-config.
+    pub fn describe(&self) -> String {
+        settings::describe(self)
+    }
+}
+
+// Switches are just on when given as a flag. Config files, variables and --no-<switch> give them a value.
+fn switch(opt: &args::Opt) -> Result<bool, ArgsError> {
+    match opt.value.as_deref() {
+        None | Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(value) => Err(ArgsError::InvalidValue(format!("--{}", opt.name), value.to_string())),
     }
 }
 
+// A switch that is needed before the options are gone through, like the others the last one wins.
+fn last_switch(opts: &[args::Opt], name: &str) -> Result<bool, ArgsError> {
+    opts.iter().rev().find(|opt| opt.name == name).map_or(Ok(false), switch)
+}
+
+// Flags like -A take a number, a value that isn't one is reported with the flag it was given to.
+fn number(opt: &args::Opt) -> Result<usize, ArgsError> {
+    let value = opt.value.as_deref().unwrap_or_default();
+
+    value
+        .parse()
+        .map_err(|_| ArgsError::InvalidValue(format!("--{}", opt.name), value.to_string()))
+}
+
+// -f reads one pattern per line. Empty lines are skipped, grep would let them match every line
+// and that is hardly ever what a pattern file is meant to do.
+fn read_patterns(file: &str) -> Result<Vec<String>, ArgsError> {
+    let contents = std::fs::read_to_string(file)
+        .map_err(|e| ArgsError::UnreadableFile(file.to_string(), e.to_string()))?;
+
+    Ok(contents.lines().filter(|line| !line.is_empty()).map(String::from).collect())
+}
+
 // Seperating the logic in a way that it can be moved in another file
 // Error handling in a user friendly way (aka not panicing with expect method)
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    // ? will return the error value from the current function for the caller to handle.
-    let contents = fs::read_to_string(config.file_path)?;
+// The returned bool tells if anything matched, main turns it into the exit status like grep does.
+pub fn run(config: Config) -> Result<bool, Box<dyn Error>> {
+    if config.interactive {
+        return tui::run(config);
+    }
 
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
+    // Locking stdout once is faster than letting every println lock it again.
+    let mut out = io::stdout().lock();
+    let colored = use_color(config.color, out.is_terminal());
+    search_to(config, colored, &mut out)
+}
+
+// Everything run does after the interactive mode, into any writer. It isn't part of the API,
+// it's only public so benches/search.rs can measure the search the command line does.
+#[doc(hidden)]
+pub fn search_to(config: Config, colored: bool, out: &mut impl Write) -> Result<bool, Box<dyn Error>> {
+    // The query is compiled once, an invalid pattern is returned as an error like a missing file.
+    let matcher = Matcher::build(&config)?;
+    let path = Path::new(&config.file_path);
+
+    if config.in_place {
+        return edit_files(&config, &matcher, path, out);
+    }
+
+    if path.is_dir() {
+        // With a directory every file is searched and the hits are prefixed with the path they came from.
+        let filter = walk_filter(&config)?;
+        let walk = walk::files(path, &filter);
+        let mut failed = report(&walk.errors);
+        let mut files = walk.files;
+        if config.index {
+            files = index::candidates(&config, path, files)?;
+        }
+
+        let searched = search_files(Arc::new(config), Arc::new(matcher), files, colored, out)?;
+        failed += searched.failed;
+        if failed > 0 {
+            return Err(SearchErrors(failed).into());
+        }
+        Ok(searched.matched)
     } else {
-        search(&config.query, &contents)
+        // stdin doesn't have a path, so it gets the same name grep gives it.
+        let name = if config.file_path == input::STDIN { Path::new(input::STDIN_NAME) } else { path };
+
+        // ? will return the error value from the current function for the caller to handle.
+        // Return Ok if everything is processed correctly, with the bool telling if something matched.
+        let reader = input::open(path, config.search_zip, config.encoding)?;
+        Ok(search_reader(&config, &matcher, reader, name, false, colored, out)?)
+    }
+}
+
+// --in-place edits the file, or every file of the directory. Files are edited one at a time,
+// so an error stops the editing before more files are changed.
+fn edit_files(config: &Config, matcher: &Matcher, path: &Path, out: &mut impl Write) -> Result<bool, Box<dyn Error>> {
+    if config.file_path == input::STDIN {
+        return Err("--in-place needs a file or a directory to edit, not standard input".into());
+    }
+
+    if !path.is_dir() {
+        return Ok(edit::edit_file(config, matcher, path, out)?);
+    }
+
+    let filter = walk_filter(config)?;
+    let walk = walk::files(path, &filter);
+    let failed = report(&walk.errors);
+    let mut edited = false;
+
+    for file in walk.files {
+        match edit::edit_file(config, matcher, &file, out) {
+            Ok(changed) => edited |= changed,
+            // Files that aren't valid UTF-8 are skipped, the same as when searching a directory.
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {}
+            Err(e) => return Err(format!("{}: {e}", file.display()).into()),
+        }
+    }
+
+    if failed > 0 {
+        return Err(SearchErrors(failed).into());
+    }
+    Ok(edited)
+}
+
+// Some files or directories couldn't be searched. They were already reported one by one on stderr and the search went on,
+// this only makes the exit status 2 at the end like it is with grep.
+#[derive(Debug)]
+pub struct SearchErrors(pub usize);
+
+impl fmt::Display for SearchErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            1 => write!(f, "1 path couldn't be searched"),
+            failed => write!(f, "{failed} paths couldn't be searched"),
+        }
+    }
+}
+
+impl Error for SearchErrors {}
+
+// Prints the errors on stderr and returns how many there were.
+fn report(errors: &[PathError]) -> usize {
+    for error in errors {
+        eprintln!("{error}");
+    }
+    errors.len()
+}
+
+// What searching the files of a directory came to.
+#[derive(Debug)]
+struct Searched {
+    matched: bool,
+    // The files that couldn't be searched, they were reported on stderr.
+    failed: usize,
+}
+
+// Searching and --in-place walk directories with the same rules.
+fn walk_filter(config: &Config) -> Result<walk::Filter, globset::Error> {
+    Ok(walk::Filter::build(&config.include, &config.exclude)?
+        .hidden(config.hidden)
+        .ignore_files(!config.no_ignore))
+}
+
+// "minigrep index build DIR" writes the index that --index uses, or updates the one that is there.
+pub fn build_index(dir: &Path) -> Result<IndexStats, Box<dyn Error>> {
+    index::build(dir)
+}
+
+// Searches the files on a thread pool. Every file is searched by one worker into its own buffer,
+// and the buffers are printed in the same order as the files, no matter which worker finishes first.
+fn search_files(
+    config: Arc<Config>,
+    matcher: Arc<Matcher>,
+    files: Vec<PathBuf>,
+    colored: bool,
+    out: &mut impl Write,
+) -> io::Result<Searched> {
+    let pool = pool::ThreadPool::new(config.jobs)
+        .map_err(|e| io::Error::new(e.kind(), format!("couldn't start the search threads: {e}")))?;
+    let (sender, receiver) = mpsc::channel();
+    // Set when printing fails. Nobody is going to see the rest, so the workers skip the files that are still queued.
+    let cancelled = Arc::new(AtomicBool::new(false));
+    // Files are only queued this far ahead of the one that is printed next. The buffers of the files that finished early
+    // wait in memory, without a limit a slow terminal would let the results of the whole tree pile up.
+    let window = config.jobs * 4;
+    let total = files.len();
+    let mut files = files.into_iter().enumerate().peekable();
+
+    // Results that arrive too early wait here until every file before them has been printed.
+    let mut waiting = BTreeMap::new();
+    let mut next = 0;
+    let mut searched = Searched { matched: false, failed: 0 };
+
+    while next < total {
+        while let Some((index, file)) = files.next_if(|(index, _)| *index < next + window) {
+            let config = Arc::clone(&config);
+            let matcher = Arc::clone(&matcher);
+            let sender = sender.clone();
+            let cancelled = Arc::clone(&cancelled);
+
+            pool.execute(move || {
+                if cancelled.load(Ordering::Relaxed) {
+                    return;
+                }
+                // A panic would leave this index without a result, and the files after it would never be printed.
+                let result = caught(|| search_file(&config, &matcher, &file, colored)).map_err(|error| PathError { path: file, error });
+                // Sending only fails when printing already failed, then nobody is waiting for the result anymore.
+                let _ = sender.send((index, result));
+            });
+        }
+
+        // The file at next is queued and every job sends a result, and this function still holds a sender,
+        // so receiving can only end with a result.
+        let (index, result) = receiver.recv().expect("a sender is still alive");
+        waiting.insert(index, result);
+
+        while let Some(result) = waiting.remove(&next) {
+            // A file that can't be read is reported and the others are still searched, like grep does.
+            // Only failing to print stops the search.
+            match result {
+                Ok((printed, matched)) => {
+                    searched.matched |= matched;
+                    if let Err(e) = out.write_all(&printed) {
+                        cancelled.store(true, Ordering::Relaxed);
+                        return closed(e, searched);
+                    }
+                }
+                Err(e) => searched.failed += report(&[e]),
+            }
+            next += 1;
+        }
+    }
+
+    Ok(searched)
+}
+
+// Turns a panic into an error, so the caller gets a result no matter what.
+fn caught<T>(f: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(io::Error::other(format!("searching panicked: {message}")))
+    })
+}
+
+// Searches one file of a directory and returns what would have been printed and whether the file matched.
+fn search_file(config: &Config, matcher: &Matcher, file: &Path, colored: bool) -> io::Result<(Vec<u8>, bool)> {
+    let reader = input::open(file, config.search_zip, config.encoding)?;
+    let mut out = Vec::new();
+
+    match search_reader(config, matcher, reader, file, true, colored, &mut out) {
+        // Input that can't be decoded at all, like a broken .gz file, is skipped instead of stopping the whole search.
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Ok((Vec::new(), false)),
+        result => result.map(|matched| (out, matched)),
+    }
+}
+
+// Searches one input line by line, the matches are printed as soon as they are found.
+fn search_reader(
+    config: &Config,
+    matcher: &Matcher,
+    mut reader: impl BufRead,
+    file: &Path,
+    with_file_name: bool,
+    colored: bool,
+    out: &mut impl Write,
+) -> io::Result<bool> {
+    let mut printer = output::Printer::new(config, file, with_file_name, colored, out);
+
+    // A NUL byte at the start makes the whole input binary, not just the lines after it.
+    if input::is_binary(&mut reader)? {
+        printer.binary();
+    }
+
+    // -v lines aren't close to anything, so they are printed in order as usual.
+    if matcher.is_fuzzy() && !config.invert {
+        return search_ranked(config, matcher, reader, printer);
+    }
+    if scannable(config) {
+        return search_scanned(config, matcher, reader, printer);
+    }
+
+    search_lines(config, matcher, reader, printer)
+}
+
+fn search_lines(
+    config: &Config,
+    matcher: &Matcher,
+    reader: impl BufRead,
+    mut printer: output::Printer<impl Write>,
+) -> io::Result<bool> {
+    let mut lines = input::read_lines(reader).lossy();
+    while let Some(line) = lines.next() {
+        let line = line?;
+        if is_binary(config, &lines, &line) {
+            printer.binary();
+        }
+        // After -m matches the rest of the lines can only be context, they aren't searched anymore.
+        let found = if printer.is_full() { None } else { select(config, matcher, &line.text) };
+        if let Err(e) = print_line(config, matcher, &mut printer, line.as_line(), found.as_deref()) {
+            return closed(e, printer.matched());
+        }
+
+        // -l and -m can stop early, the rest of the file wouldn't change the output.
+        if printer.is_done() {
+            break;
+        }
+    }
+
+    let matched = printer.matched();
+    printer.finish().or_else(|e| closed(e, matched))
+}
+
+// Only a plain query can be scanned for, scan::search finds one literal text and nothing else: not -i, regexes,
+// fuzzy matching, expressions or more than one pattern. -v and context need the lines that don't match, which the scan
+// skips. stdin and -z input stay streamed line by line, the scan needs the whole text in memory and those can be endless
+// or much bigger than the file on the disk.
+fn scannable(config: &Config) -> bool {
+    config.patterns.len() == 1
+        && !config.ignore_case
+        && !config.regex
+        && config.fuzzy.is_none()
+        && !config.expression
+        && !config.invert
+        && config.before_context == 0
+        && config.after_context == 0
+        && config.file_path != input::STDIN
+        && !config.search_zip
+}
+
+// Reads the whole file and lets the memchr scan find the matching lines, the lines in between are never split.
+// Only the lines it found are searched again with the matcher, for everything there is to highlight.
+// A file that isn't valid UTF-8 or has a NUL in it is searched line by line after all, that's where binary input is handled.
+fn search_scanned(
+    config: &Config,
+    matcher: &Matcher,
+    mut reader: impl BufRead,
+    mut printer: output::Printer<impl Write>,
+) -> io::Result<bool> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let contents = match std::str::from_utf8(&bytes) {
+        Ok(contents) if !contents.contains('\0') => contents,
+        _ => return search_lines(config, matcher, &bytes[..], printer),
     };
 
-    for line in results {
-        println!("{line}");
+    for found in scan::search(&config.patterns[0], contents) {
+        // Without context -m is done as soon as it's full (-m0 before the first match), and -l after the first match.
+        if printer.is_done() {
+            break;
+        }
+        let line = Line { index: found.line_number - 1, offset: found.offset, text: found.text };
+        let columns = matcher.find_all(line.text).unwrap_or_default();
+        if let Err(e) = print_line(config, matcher, &mut printer, line, Some(&columns)) {
+            return closed(e, printer.matched());
+        }
+    }
+
+    let matched = printer.matched();
+    printer.finish().or_else(|e| closed(e, matched))
+}
+
+// The reader of the output went away, like with `minigrep duct poem.txt | head -1`. That isn't an error,
+// there's just nobody left to see the rest, so the search ends quietly with what was found so far.
+fn closed<T>(e: io::Error, so_far: T) -> io::Result<T> {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        Ok(so_far)
+    } else {
+        Err(e)
+    }
+}
+
+// --fuzzy prints the closest lines first, so the whole input is searched before anything is printed.
+// The lines aren't in order anymore, which is why context can't be used with it. -m keeps the closest lines.
+fn search_ranked(
+    config: &Config,
+    matcher: &Matcher,
+    reader: impl BufRead,
+    mut printer: output::Printer<impl Write>,
+) -> io::Result<bool> {
+    let mut ranked = Vec::new();
+
+    let mut lines = input::read_lines(reader).lossy();
+    while let Some(line) = lines.next() {
+        let line = line?;
+        // The lines are printed out of order, so one binary line makes the whole input binary.
+        if is_binary(config, &lines, &line) {
+            printer.binary();
+        }
+        if let Some((distance, found)) = matcher.find_fuzzy(&line.text) {
+            ranked.push((distance, line, found));
+        }
+    }
+    // The sort is stable, lines that are as close as each other stay in the order they were in.
+    ranked.sort_by_key(|(distance, _, _)| *distance);
+
+    for (_, line, found) in ranked {
+        if printer.is_full() || printer.is_done() {
+            break;
+        }
+        if let Err(e) = print_line(config, matcher, &mut printer, line.as_line(), Some(&found)) {
+            return closed(e, printer.matched());
+        }
+    }
+
+    let matched = printer.matched();
+    printer.finish().or_else(|e| closed(e, matched))
+}
+
+// Like grep, a line with a NUL byte or with bytes that aren't UTF-8 makes the rest of the input binary.
+// --lossy reads the invalid bytes as U+FFFD instead, so text with a few broken characters can still be printed.
+fn is_binary<R>(config: &Config, lines: &input::ReadLines<R>, line: &input::OwnedLine) -> bool {
+    line.text.contains('\0') || (lines.found_invalid() && !config.lossy)
+}
+
+// With --replace the matching lines are printed with the matches replaced, and the replacements are highlighted.
+fn print_line(
+    config: &Config,
+    matcher: &Matcher,
+    printer: &mut output::Printer<impl Write>,
+    line: Line,
+    found: Option<&[Range<usize>]>,
+) -> io::Result<()> {
+    match (&config.replace, found) {
+        (Some(replacement), Some(found)) if !found.is_empty() => {
+            let (text, replaced) = matcher.replace(line.text, replacement);
+            printer.line(Line { text: &text, ..line }, Some(&replaced))
+        }
+        _ => printer.line(line, found),
+    }
+}
+
+// Decides if a line is selected and what to highlight in it. -v selects the lines without a match,
+// and those have nothing to highlight.
+fn select(config: &Config, matcher: &Matcher, text: &str) -> Option<Vec<Range<usize>>> {
+    match (matcher.find_all(text), config.invert) {
+        (Some(found), false) => Some(found),
+        (None, true) => Some(Vec::new()),
+        _ => None,
     }
+}
+
+// --color=always and never win over everything, NO_COLOR (https://no-color.org) only turns off the automatic coloring.
+fn use_color(color: Color, is_terminal: bool) -> bool {
+    match color {
+        Color::Always => true,
+        Color::Never => false,
+        Color::Auto => is_terminal && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+    }
+}
+
+// One matching line and where the match was found. This is also what --output json prints.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Match<'a> {
+    // The search functions leave this empty, run fills it in with the file that was searched.
+    #[serde(serialize_with = "lossy_path")]
+    pub file: Option<&'a Path>,
+    // One based, like editors and grep show it.
+    pub line_number: usize,
+    // Byte offset of the start of the line in the searched contents.
+    pub offset: usize,
+    // Byte range of the first match inside the line.
+    pub columns: Range<usize>,
+    pub text: &'a str,
+}
+
+// serde refuses paths that aren't valid UTF-8, which would stop the whole search because of one odd file name.
+// The broken bytes are replaced instead, the same way the text output shows them.
+fn lossy_path<S: serde::Serializer>(file: &Option<&Path>, serializer: S) -> Result<S::Ok, S::Error> {
+    match file {
+        Some(file) => serializer.serialize_str(&file.to_string_lossy()),
+        None => serializer.serialize_none(),
+    }
+}
+
+// A line of the searched contents. Context lines don't match anything, so they are just lines.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct Line<'a> {
+    // Zero based, the output adds one to it like editors do.
+    pub index: usize,
+    // Byte offset of the start of the line in the searched contents.
+    pub offset: usize,
+    pub text: &'a str,
+}
+
+impl<'a> Line<'a> {
+    pub(crate) fn matched(self, columns: Range<usize>) -> Match<'a> {
+        Match {
+            file: None,
+            line_number: self.index + 1,
+            offset: self.offset,
+            columns,
+            text: self.text,
+        }
+    }
+}
+
+// Same as contents.lines(), but every line knows its index and where it starts.
+pub(crate) fn lines(contents: &str) -> impl Iterator<Item = Line<'_>> {
+    let mut offset = 0;
+
+    contents.split_inclusive('\n').enumerate().map(move |(index, raw)| {
+        // The line ending isn't part of the text, "\r\n" is stripped the same way as "\n".
+        let text = match raw.strip_suffix('\n') {
+            Some(text) => text.strip_suffix('\r').unwrap_or(text),
+            None => raw,
+        };
+        let line = Line { index, offset, text };
+        offset += raw.len();
 
-    // Return Ok if everything is processed correctly.
-    // Because end product is just a println Ok doesn't need to contain any other
-    // thing other than "()" which is specified as a Result Ok value.
-    Ok(())
+        line
+    })
 }
 
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+// The lines that contain the query, see scan::search for how they are found.
+// The command line uses the same scan for a plain query in a file, see search_scanned.
+pub fn search<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    scan::search(query, contents)
+}
+
+// The simple way of doing the same: split the contents into lines and look at every one of them.
+// This is how search used to work. It isn't part of the API, it's only public so benches/search.rs
+// can compare the two, and the tests of scan use it to check that the results stay the same.
+#[doc(hidden)]
+pub fn search_by_line<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
     let mut results = Vec::new();
 
-    for line in contents.lines() {
-        if line.contains(query) {
-            results.push(line);
+    for line in lines(contents) {
+        if let Some(columns) = find(query, line.text) {
+            results.push(line.matched(columns));
         }
     }
 
     results
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
+fn find(query: &str, line: &str) -> Option<Range<usize>> {
+    line.find(query).map(|start| start..start + query.len())
+}
+
+// Uses Unicode case folding, so "straße" finds "STRASSE" and "ΟΔΟΣ" finds "οδος".
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    search_folded(query, contents, CaseFold::Unicode)
+}
+
+// Same as search_case_insensitive, but the way the text is folded can be chosen.
+pub fn search_folded<'a>(query: &str, contents: &'a str, mode: CaseFold) -> Vec<Match<'a>> {
+    let query = fold::fold(query, mode);
     let mut results = Vec::new();
 
-    for line in contents.lines() {
-        // Works as intended, but will break with certain unicodes 
-        if line.to_lowercase().contains(&query) {
-            results.push(line);
+    for line in lines(contents) {
+        if let Some(columns) = find_folded(&query, line.text, mode) {
+            results.push(line.matched(columns));
         }
     }
 
     results
 }
 
+// The query has to be folded already, the line is folded here.
+fn find_folded(query: &str, line: &str, mode: CaseFold) -> Option<Range<usize>> {
+    let (folded, positions) = fold::fold_with_positions(line, mode);
+    let start = folded.find(query)?;
+
+    Some(folded_range(line, &positions, start, query.len()))
+}
+
+// Turns a match in the folded line back into a range of the original line.
+fn folded_range(line: &str, positions: &[usize], start: usize, len: usize) -> Range<usize> {
+    if len == 0 {
+        return positions[start]..positions[start];
+    }
+
+    // The match can end in the middle of a folded character ("s" of the "ss" that came from "ß"),
+    // so the end is taken from the character the last matched byte belongs to.
+    let last = positions[start + len - 1];
+    let end = last + line[last..].chars().next().map_or(0, char::len_utf8);
+
+    positions[start]..end
+}
+
+// Finds the lines that have text within max_distance edits (added, removed or changed characters) of the query,
+// so "conection" still finds "connection". The closest lines come first, and lines that are as close keep their order.
+pub fn search_fuzzy<'a>(query: &str, contents: &'a str, max_distance: usize) -> Vec<Match<'a>> {
+    let query: Vec<char> = query.chars().collect();
+    let mut results: Vec<(usize, Match)> = lines(contents)
+        .filter_map(|line| fuzzy::find(&query, line.text, max_distance).map(|(distance, columns)| (distance, line.matched(columns))))
+        .collect();
+
+    results.sort_by_key(|(distance, _)| *distance);
+    results.into_iter().map(|(_, found)| found).collect()
+}
+
+// Regex version of search. The pattern is compiled by the caller, so the same Regex can be reused
+// and the case sensitivity can be decided with RegexBuilder.
+pub fn search_regex<'a>(pattern: &Regex, contents: &'a str) -> Vec<Match<'a>> {
+    lines(contents)
+        .filter_map(|line| pattern.find(line.text).map(|found| line.matched(found.range())))
+        .collect()
+}
+
 // Old implementation, implemented in Config struct
 // For this sized project, separating parse functionality like this might be too much.
 // fn parse_config(args: &[String]) -> Config {
@@ -101,19 +988,26 @@ pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a st
 #[cfg(test)]
 mod tests {
     use super::*;
+    use regex::RegexBuilder;
+    use testing::TempDir;
+
+    // Most tests only care about which lines matched.
+    fn texts<'a>(results: Vec<Match<'a>>) -> Vec<&'a str> {
+        results.iter().map(|line| line.text).collect()
+    }
 
     #[test]
     fn case_insensitive() {
         let query = "rUsT";
         let contents = "\
-            Rust:
-            safe, fast, productive.
-            Pick three.
-            Trust me.";
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
 
         assert_eq!(
             vec!["Rust:", "Trust me."],
-            search_case_insensitive(query, contents)
+            texts(search_case_insensitive(query, contents))
         );
     }
 
@@ -121,10 +1015,651 @@ mod tests {
     fn case_sensitive() {
         let query = "duct";
         let contents = "\
-            Rust:
-            safe, fast, productive.
-            Pick three.";
+Rust:
+safe, fast, productive.
+Pick three.";
 
-        assert_eq!(vec!["safe, fast, productive."], search(query, contents));
+        assert_eq!(vec!["safe, fast, productive."], texts(search(query, contents)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn regex_pattern() {
+        let pattern = Regex::new(r"f[a-z]+t").unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.";
+
+        assert_eq!(vec!["safe, fast, productive."], texts(search_regex(&pattern, contents)));
+    }
+
+    #[test]
+    fn regex_anchors_and_classes() {
+        let pattern = Regex::new(r"^[A-Z]\w+:$").unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive:
+Pick three.";
+
+        assert_eq!(vec!["Rust:"], texts(search_regex(&pattern, contents)));
+    }
+
+    #[test]
+    fn regex_capture_groups() {
+        let pattern = Regex::new(r"(Pick|Trust) (three|me)\.").unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        assert_eq!(vec!["Pick three.", "Trust me."], texts(search_regex(&pattern, contents)));
+    }
+
+    #[test]
+    fn regex_case_insensitive() {
+        let pattern = RegexBuilder::new("^rUsT")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Trust me.";
+
+        assert_eq!(vec!["Rust:"], texts(search_regex(&pattern, contents)));
+    }
+
+    #[test]
+    fn match_records() {
+        let contents = "Rust:\r\nsafe, fast, productive.\nPick three.";
+
+        assert_eq!(
+            vec![
+                Match { file: None, line_number: 2, offset: 7, columns: 17..18, text: "safe, fast, productive." },
+                Match { file: None, line_number: 3, offset: 31, columns: 2..3, text: "Pick three." },
+            ],
+            search_case_insensitive("C", contents)
+        );
+    }
+
+    #[test]
+    fn match_columns() {
+        let contents = "Trust me, rust is fast.";
+        let pattern = Regex::new(r"f\w+").unwrap();
+
+        assert_eq!(1..5, search("rust", contents)[0].columns);
+        assert_eq!(18..22, search_regex(&pattern, contents)[0].columns);
+    }
+
+    #[test]
+    fn case_insensitive_unicode() {
+        let contents = "\
+Die Straße ist lang.
+ΟΔΟΣ ΕΡΜΟΥ
+10 µm wide";
+
+        assert_eq!(vec!["Die Straße ist lang."], texts(search_case_insensitive("STRASSE", contents)));
+        assert_eq!(vec!["ΟΔΟΣ ΕΡΜΟΥ"], texts(search_case_insensitive("οδος", contents)));
+        assert_eq!(vec!["10 µm wide"], texts(search_case_insensitive("μM", contents)));
+    }
+
+    #[test]
+    fn case_insensitive_scripts() {
+        // The greetings from the string_test crate. Scripts without case only match themselves.
+        let contents = "\
+السلام عليكم
+Dobrý den
+Hello
+שָׁלוֹם
+नमस्ते
+こんにちは
+안녕하세요
+你好
+Olá
+Здравствуйте
+Hola
+Ä Ö Å Ø Æ";
+
+        assert_eq!(vec!["Dobrý den"], texts(search_case_insensitive("DOBRÝ", contents)));
+        assert_eq!(vec!["Olá"], texts(search_case_insensitive("OLÁ", contents)));
+        assert_eq!(vec!["Здравствуйте"], texts(search_case_insensitive("здравСТВУЙТЕ", contents)));
+        assert_eq!(vec!["Ä Ö Å Ø Æ"], texts(search_case_insensitive("ä ö å ø æ", contents)));
+        assert_eq!(vec!["Hello", "Hola"], texts(search_case_insensitive("H", contents)));
+
+        assert_eq!(vec!["السلام عليكم"], texts(search_case_insensitive("السلام", contents)));
+        for uncased in ["שָׁלוֹם", "नमस्ते", "こんにちは", "안녕하세요", "你好"] {
+            assert_eq!(vec![uncased], texts(search_case_insensitive(uncased, contents)));
+        }
+    }
+
+    #[test]
+    fn case_insensitive_lowercase_mode() {
+        let contents = "Die Straße ist lang.";
+
+        assert!(search_folded("STRASSE", contents, CaseFold::Lowercase).is_empty());
+        assert_eq!(vec![contents], texts(search_folded("STRAßE", contents, CaseFold::Lowercase)));
+    }
+
+    #[test]
+    fn case_insensitive_columns_for_folded_characters() {
+        let contents = "Die Straße ist lang.";
+        let found = &search_case_insensitive("trass", contents)[0];
+
+        // The match ends in the middle of "ß", so the whole "ß" is part of it.
+        assert_eq!("traß", &found.text[found.columns.clone()]);
+    }
+
+    #[test]
+    fn case_insensitive_columns_point_into_the_original_line() {
+        // "İ" lowercases into two characters, the columns still have to point at the original text.
+        let contents = "İstanbul rust";
+        let found = &search_case_insensitive("RUST", contents)[0];
+
+        assert_eq!("rust", &found.text[found.columns.clone()]);
+    }
+
+    #[test]
+    fn json_lines() {
+        let contents = "Rust:\nsafe, fast, productive.";
+        let mut found = search("fast", contents).remove(0);
+        found.file = Some(Path::new("poem.txt"));
+
+        assert_eq!(
+            r#"{"file":"poem.txt","line_number":2,"offset":6,"columns":{"start":6,"end":10},"text":"safe, fast, productive."}"#,
+            serde_json::to_string(&found).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn json_with_a_non_utf8_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut found = search("fast", "safe, fast, productive.").remove(0);
+        found.file = Some(Path::new(OsStr::from_bytes(b"po\xffem.txt")));
+
+        assert!(serde_json::to_string(&found).unwrap().starts_with("{\"file\":\"po\u{fffd}em.txt\","));
+    }
+
+    fn build(args: &[&str]) -> Result<Config, ArgsError> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        // The config files and variables of whoever runs the tests shouldn't change the results.
+        Config::build_layered(&args, Vec::new())
+    }
+
+    #[test]
+    fn build_reads_query_and_path() {
+        let config = build(&["minigrep", "duct", "poem.txt"]).unwrap();
+
+        assert_eq!(vec!["duct"], config.patterns);
+        assert_eq!("poem.txt", config.file_path);
+        assert!(!config.line_number && !config.count && !config.regex);
+    }
+
+    #[test]
+    fn build_reads_stdin_without_path() {
+        assert_eq!("-", build(&["minigrep", "duct"]).unwrap().file_path);
+        assert_eq!("-", build(&["minigrep", "duct", "-"]).unwrap().file_path);
+    }
+
+    #[test]
+    fn build_reads_flags_in_any_position() {
+        let config = build(&["minigrep", "-n", "duct", "--count", "poem.txt", "-i"]).unwrap();
+
+        assert!(config.ignore_case && config.line_number && config.count);
+    }
+
+    #[test]
+    fn build_reads_combined_flags() {
+        let config = build(&["minigrep", "-ic", "duct", "poem.txt"]).unwrap();
+
+        assert!(config.ignore_case && config.count && !config.line_number);
+    }
+
+    #[test]
+    fn build_query_after_double_dash() {
+        let config = build(&["minigrep", "-n", "--", "-i", "poem.txt"]).unwrap();
+
+        assert_eq!(vec!["-i"], config.patterns);
+        assert!(config.line_number);
+    }
+
+    #[test]
+    fn build_reads_context() {
+        let config = build(&["minigrep", "-C", "2", "-A1", "-b", "duct", "poem.txt"]).unwrap();
+
+        assert_eq!((2, 1), (config.before_context, config.after_context));
+        assert!(config.byte_offset);
+    }
+
+    #[test]
+    fn build_reads_case_fold() {
+        assert_eq!(CaseFold::Unicode, build(&["minigrep", "duct", "poem.txt"]).unwrap().case_fold);
+        assert_eq!(
+            CaseFold::Lowercase,
+            build(&["minigrep", "--case-fold", "lowercase", "duct", "poem.txt"]).unwrap().case_fold
+        );
+        assert_eq!(
+            Some(ArgsError::InvalidValue("--case-fold".to_string(), "tr".to_string())),
+            build(&["minigrep", "--case-fold=tr", "duct", "poem.txt"]).err()
+        );
+    }
+
+    #[test]
+    fn build_reads_color() {
+        assert_eq!(Color::Auto, build(&["minigrep", "duct", "poem.txt"]).unwrap().color);
+        assert_eq!(Color::Always, build(&["minigrep", "--color=always", "duct", "poem.txt"]).unwrap().color);
+        assert_eq!(Color::Never, build(&["minigrep", "--color", "never", "duct", "poem.txt"]).unwrap().color);
+    }
+
+    #[test]
+    fn color_choice() {
+        assert!(use_color(Color::Always, false));
+        assert!(!use_color(Color::Never, true));
+        assert!(!use_color(Color::Auto, false));
+    }
+
+    #[test]
+    fn find_all_matches() {
+        let config = build(&["minigrep", "-i", "ss", "poem.txt"]).unwrap();
+        let matcher = Matcher::build(&config).unwrap();
+
+        // "ß" is two bytes, the second match covers all of it.
+        assert_eq!(Some(vec![2..4, 9..11]), matcher.find_all("Kiss Straße"));
+    }
+
+    #[test]
+    fn build_reads_jobs() {
+        assert_eq!(3, build(&["minigrep", "-j", "3", "duct", "src"]).unwrap().jobs);
+        assert!(build(&["minigrep", "duct", "src"]).unwrap().jobs >= 1);
+        assert_eq!(
+            Some(ArgsError::InvalidValue("--jobs".to_string(), "0".to_string())),
+            build(&["minigrep", "-j0", "duct", "src"]).err()
+        );
+    }
+
+    #[test]
+    fn parallel_search_keeps_file_order() {
+        let root = TempDir::new("parallel");
+
+        // Bigger files take longer, so the workers finish in a different order than the files are in.
+        let mut files = Vec::new();
+        for number in 0..20 {
+            let file = root.join(format!("{number:02}.txt"));
+            let lines = "filler\n".repeat((20 - number) * 500);
+            std::fs::write(&file, format!("{lines}needle {number}\n")).unwrap();
+            files.push(file);
+        }
+
+        let config = Arc::new(build(&["minigrep", "-j4", "needle", root.to_str().unwrap()]).unwrap());
+        let matcher = Arc::new(Matcher::build(&config).unwrap());
+        let mut out = Vec::new();
+        assert!(search_files(config, matcher, files.clone(), false, &mut out).unwrap().matched);
+
+        let expected: Vec<String> = files
+            .iter()
+            .enumerate()
+            .map(|(number, file)| format!("{}:needle {number}", file.display()))
+            .collect();
+        assert_eq!(expected, String::from_utf8(out).unwrap().lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn unreadable_file_doesnt_stop_the_search() {
+        let root = TempDir::new("unreadable");
+        std::fs::write(root.join("a.txt"), "needle a").unwrap();
+        std::fs::write(root.join("c.txt"), "needle c").unwrap();
+        let files = vec![root.join("a.txt"), root.join("b.txt"), root.join("c.txt")];
+
+        let config = Arc::new(build(&["minigrep", "needle", root.to_str().unwrap()]).unwrap());
+        let matcher = Arc::new(Matcher::build(&config).unwrap());
+        let mut out = Vec::new();
+
+        let searched = search_files(config, matcher, files.clone(), false, &mut out).unwrap();
+
+        assert!(searched.matched);
+        assert_eq!(1, searched.failed);
+        assert_eq!(
+            format!("{}:needle a\n{}:needle c\n", files[0].display(), files[2].display()),
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn scanned_search_prints_what_the_line_search_prints() {
+        let root = TempDir::new("scanned");
+        let text = root.join("text.txt");
+        std::fs::write(&text, "Rust:\r\nsafe, fast, productive.\nduct tape, duct tape\n\nStraße duct\nPick three.").unwrap();
+        let binary = root.join("binary.bin");
+        std::fs::write(&binary, "duct\0tape\nduct").unwrap();
+
+        // A query without special characters finds the same with --regex, which is always searched line by line.
+        for flags in [&[][..], &["-n", "-b"], &["-c"], &["-l"], &["-m", "1"], &["--replace", "[tape]"], &["--output", "json"], &["--color", "always"]] {
+            for file in [&text, &binary] {
+                let args: Vec<&str> = ["minigrep"].iter().chain(flags).chain(&["duct", file.to_str().unwrap()]).copied().collect();
+                let config = build(&args).unwrap();
+                assert!(scannable(&config));
+                let matcher = Matcher::build(&config).unwrap();
+                let scanned = search_file(&config, &matcher, file, false).unwrap();
+
+                let config = build(&[&args[..], &["--regex"]].concat()).unwrap();
+                let matcher = Matcher::build(&config).unwrap();
+                assert_eq!(search_file(&config, &matcher, file, false).unwrap(), scanned, "{flags:?} {}", file.display());
+            }
+        }
+
+        assert!(!scannable(&build(&["minigrep", "-v", "duct", text.to_str().unwrap()]).unwrap()));
+        assert!(!scannable(&build(&["minigrep", "-C1", "duct", text.to_str().unwrap()]).unwrap()));
+        assert!(!scannable(&build(&["minigrep", "duct"]).unwrap()));
+    }
+
+    #[test]
+    fn panicking_search_is_an_error() {
+        let error = caught::<()>(|| panic!("bad file")).unwrap_err();
+        assert_eq!("searching panicked: bad file", error.to_string());
+        assert_eq!(7, caught(|| Ok(7)).unwrap());
+    }
+
+    // Takes the first write and fails every one after it, like a pipe whose reader went away.
+    struct ClosedPipe(usize);
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += 1;
+            if self.0 > 1 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failing_write_stops_the_search() {
+        let root = TempDir::new("closed");
+        let mut files = Vec::new();
+        for number in 0..100 {
+            let file = root.join(format!("{number:03}.txt"));
+            std::fs::write(&file, "needle").unwrap();
+            files.push(file);
+        }
+
+        let config = Arc::new(build(&["minigrep", "-j2", "needle", root.to_str().unwrap()]).unwrap());
+        let matcher = Arc::new(Matcher::build(&config).unwrap());
+        let mut out = ClosedPipe(0);
+
+        // Nobody reads the output anymore, which isn't an error. Something matched before that, so the exit status is still 0.
+        assert!(search_files(config, matcher, files, false, &mut out).unwrap().matched);
+        assert_eq!(2, out.0);
+    }
+
+    #[test]
+    fn closed_output_ends_quietly() {
+        let contents = "duct\n".repeat(10);
+        let config = build(&["minigrep", "duct", "poem.txt"]).unwrap();
+        let matcher = Matcher::build(&config).unwrap();
+        let mut out = ClosedPipe(0);
+        assert!(search_reader(&config, &matcher, contents.as_bytes(), Path::new("poem.txt"), false, false, &mut out).unwrap());
+
+        let config = build(&["minigrep", "-c", "nope", "poem.txt"]).unwrap();
+        let matcher = Matcher::build(&config).unwrap();
+        let mut out = ClosedPipe(1);
+        assert!(!search_reader(&config, &matcher, contents.as_bytes(), Path::new("poem.txt"), false, false, &mut out).unwrap());
+    }
+
+    #[test]
+    fn build_reads_selection_flags() {
+        let config = build(&["minigrep", "-vl", "-m", "3", "duct", "poem.txt"]).unwrap();
+        assert!(config.invert);
+        assert!(config.files_with_matches);
+        assert!(!config.files_without_match);
+        assert_eq!(Some(3), config.max_count);
+        assert_eq!(None, build(&["minigrep", "duct", "poem.txt"]).unwrap().max_count);
+    }
+
+    #[test]
+    fn build_reads_the_encoding() {
+        assert_eq!(None, build(&["minigrep", "duct", "poem.txt"]).unwrap().encoding);
+        assert_eq!(None, build(&["minigrep", "--encoding", "auto", "duct", "poem.txt"]).unwrap().encoding);
+        assert_eq!(Some(encoding_rs::SHIFT_JIS), build(&["minigrep", "--encoding=sjis", "duct", "poem.txt"]).unwrap().encoding);
+        assert_eq!(
+            Some(ArgsError::InvalidValue(String::from("--encoding"), String::from("klingon"))),
+            build(&["minigrep", "--encoding", "klingon", "duct", "poem.txt"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::Conflicts(String::from("--in-place"), String::from("--encoding"))),
+            build(&["minigrep", "--encoding", "latin1", "--replace", "x", "--in-place", "duct", "poem.txt"]).err()
+        );
+    }
+
+    #[test]
+    fn files_without_match_in_a_directory() {
+        let root = TempDir::new("without");
+        std::fs::write(root.join("a.txt"), "safe, fast, productive.").unwrap();
+        std::fs::write(root.join("b.txt"), "Pick three.").unwrap();
+        let files = vec![root.join("a.txt"), root.join("b.txt")];
+
+        let config = Arc::new(build(&["minigrep", "-L", "fast", root.to_str().unwrap()]).unwrap());
+        let matcher = Arc::new(Matcher::build(&config).unwrap());
+        let mut out = Vec::new();
+        // A file was listed, so for the exit status this counts as a match.
+        assert!(search_files(config, matcher, files.clone(), false, &mut out).unwrap().matched);
+        assert_eq!(format!("{}\n", files[1].display()), String::from_utf8(out).unwrap());
+
+        let config = Arc::new(build(&["minigrep", "-L", "e", root.to_str().unwrap()]).unwrap());
+        let matcher = Arc::new(Matcher::build(&config).unwrap());
+        let mut out = Vec::new();
+        assert!(!search_files(config, matcher, files, false, &mut out).unwrap().matched);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn compressed_files_keep_their_name() {
+        use std::io::Write;
+
+        let root = TempDir::new("zip");
+        let file = root.join("app.log.gz");
+        let mut gzip = flate2::write::GzEncoder::new(std::fs::File::create(&file).unwrap(), flate2::Compression::default());
+        gzip.write_all(b"started\nerror: disk full\n").unwrap();
+        gzip.finish().unwrap();
+
+        let config = build(&["minigrep", "-z", "-n", "error", root.to_str().unwrap()]).unwrap();
+        let matcher = Matcher::build(&config).unwrap();
+        let (out, matched) = search_file(&config, &matcher, &file, false).unwrap();
+        assert!(matched);
+        assert_eq!(format!("{}:2:error: disk full\n", file.display()), String::from_utf8(out).unwrap());
+
+        // Without -z the compressed bytes are searched like any other binary file, and the text isn't in them.
+        let config = build(&["minigrep", "error", root.to_str().unwrap()]).unwrap();
+        let matcher = Matcher::build(&config).unwrap();
+        assert!(!search_file(&config, &matcher, &file, false).unwrap().1);
+    }
+
+    #[test]
+    fn replace_literal_and_folded() {
+        let config = build(&["minigrep", "-i", "STRASSE", "poem.txt"]).unwrap();
+        let matcher = Matcher::build(&config).unwrap();
+
+        assert_eq!(("a street, a street".to_string(), vec![2..8, 12..18]), matcher.replace("a Straße, a strasse", "street"));
+        assert_eq!(("no match".to_string(), vec![]), matcher.replace("no match", "street"));
+    }
+
+    #[test]
+    fn replace_regex_groups() {
+        let config = build(&["minigrep", "--regex", r"(\w+)@(\w+)", "poem.txt"]).unwrap();
+        let matcher = Matcher::build(&config).unwrap();
+
+        let (replaced, ranges) = matcher.replace("mail ferris@rust, $3", "$1 at $2");
+        assert_eq!("mail ferris at rust, $3", replaced);
+        assert_eq!(1, ranges.len());
+        assert_eq!(5..19, ranges[0]);
+    }
+
+    #[test]
+    fn build_checks_edit_flags() {
+        let config = build(&["minigrep", "--replace", "x", "--in-place", "--backup=.orig", "duct", "poem.txt"]).unwrap();
+        assert_eq!(Some("x".to_string()), config.replace);
+        assert!(config.in_place);
+        assert_eq!(Some(".orig".to_string()), config.backup);
+
+        assert_eq!(
+            Some(ArgsError::Requires("--in-place".to_string(), "--replace".to_string())),
+            build(&["minigrep", "--in-place", "duct", "poem.txt"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::Requires("--dry-run".to_string(), "--in-place".to_string())),
+            build(&["minigrep", "--replace", "x", "--dry-run", "duct", "poem.txt"]).err()
+        );
+    }
+
+    #[test]
+    fn fuzzy_ranks_by_distance() {
+        let contents = "\
+connect timeout
+connection refused
+conecton reset
+disconnected";
+
+        let found = search_fuzzy("connection", contents, 3);
+        assert_eq!(
+            vec!["connection refused", "conecton reset", "connect timeout", "disconnected"],
+            found.iter().map(|found| found.text).collect::<Vec<_>>()
+        );
+        assert_eq!(0..10, found[0].columns);
+        assert_eq!(vec!["connection refused"], search_fuzzy("connection", contents, 0).iter().map(|found| found.text).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn fuzzy_ignores_case() {
+        let config = build(&["minigrep", "-i", "--fuzzy", "1", "STRASE", "poem.txt"]).unwrap();
+        let matcher = Matcher::build(&config).unwrap();
+
+        let (distance, found) = matcher.find_fuzzy("in Straße").unwrap();
+        assert_eq!(1, distance);
+        assert_eq!(3..10, found[0]);
+        assert_eq!(None, matcher.find_fuzzy("in Strand"));
+        assert_eq!(
+            Some(ArgsError::Conflicts("--regex".to_string(), "--fuzzy".to_string())),
+            build(&["minigrep", "--regex", "--fuzzy=1", "duct", "poem.txt"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::Conflicts("--fuzzy".to_string(), "--context".to_string())),
+            build(&["minigrep", "--fuzzy=1", "-A2", "duct", "poem.txt"]).err()
+        );
+    }
+
+    #[test]
+    fn build_reads_many_patterns() {
+        let dir = TempDir::new("patterns");
+        let file = dir.join("patterns.txt");
+        std::fs::write(&file, "three\n\nTrust\n").unwrap();
+        let file = file.to_str().unwrap();
+
+        // With -e the first positional argument is the path, not a query.
+        let config = build(&["minigrep", "-e", "duct", "-f", file, "-e", "safe", "poem.txt"]).unwrap();
+        assert_eq!(vec!["duct", "three", "Trust", "safe"], config.patterns);
+        assert_eq!("poem.txt", config.file_path);
+
+        assert!(matches!(
+            build(&["minigrep", "-f", "no/such/patterns.txt"]).err(),
+            Some(ArgsError::UnreadableFile(file, _)) if file == "no/such/patterns.txt"
+        ));
+    }
+
+    fn layer(source: Source, opts: &[(&'static str, &str)]) -> Layer {
+        let opts = opts.iter().map(|(name, value)| args::Opt { name, value: Some(value.to_string()) }).collect();
+        Layer { source, opts }
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let args: Vec<String> = ["minigrep", "-C", "1", "--no-line-number", "duct"].iter().map(|arg| arg.to_string()).collect();
+        let layers = vec![
+            layer(Source::UserFile(PathBuf::from("config.toml")), &[("color", "never"), ("context", "5"), ("include", "*.rs")]),
+            layer(Source::ProjectFile(PathBuf::from(".minigreprc")), &[("line-number", "true"), ("include", "*.md")]),
+            layer(Source::Env(String::from("MINIGREP_COLOR")), &[("color", "always")]),
+        ];
+        let config = Config::build_layered(&args, layers).unwrap();
+
+        assert_eq!(Color::Always, config.color);
+        assert_eq!((1, 1), (config.before_context, config.after_context));
+        // --no-line-number turns off what the project file turned on.
+        assert!(!config.line_number);
+        // Lists are added together.
+        assert_eq!(vec!["*.rs", "*.md"], config.include);
+
+        assert_eq!(Some(&Source::Env(String::from("MINIGREP_COLOR"))), config.sources.get("color"));
+        assert_eq!(Some(&Source::CommandLine), config.sources.get("before-context"));
+        assert_eq!(None, config.sources.get("jobs"));
+    }
+
+    #[test]
+    fn layers_are_checked_like_flags() {
+        let args = vec![String::from("minigrep"), String::from("duct")];
+        let layers = vec![layer(Source::ProjectFile(PathBuf::from(".minigreprc")), &[("count", "maybe")])];
+
+        assert_eq!(
+            Some(ArgsError::InvalidValue("--count".to_string(), "maybe".to_string())),
+            Config::build_layered(&args, layers).err()
+        );
+    }
+
+    #[test]
+    fn print_config_shows_the_sources() {
+        let args: Vec<String> = ["minigrep", "--print-config", "-i"].iter().map(|arg| arg.to_string()).collect();
+        let layers = vec![layer(Source::ProjectFile(PathBuf::from("/work/.minigreprc")), &[("exclude", "target/**")])];
+        let config = Config::build_layered(&args, layers).unwrap();
+        let described = config.describe();
+
+        assert!(config.print_config);
+        assert!(described.contains(&format!("{:<32} # command line\n", "ignore-case = true")));
+        assert!(described.contains(&format!("{:<32} # default\n", "color = \"auto\"")));
+        assert!(described.contains(&format!("{:<32} # project config /work/.minigreprc\n", "exclude = [\"target/**\"]")));
+        assert!(described.contains("# max-count isn't set"));
+    }
+
+    #[test]
+    fn interactive_can_be_turned_off() {
+        let config = build(&["minigrep", "--interactive", "--no-interactive", "foo", "a.txt"]).unwrap();
+        assert!(!config.interactive);
+        // Without --interactive the first argument is the query again.
+        assert_eq!(vec![String::from("foo")], config.patterns);
+        assert_eq!("a.txt", config.file_path);
+    }
+
+    #[test]
+    fn print_config_can_be_turned_off() {
+        assert!(!build(&["minigrep", "--no-print-config", "foo", "a.txt"]).unwrap().print_config);
+        assert!(!build(&["minigrep", "--print-config", "--no-print-config", "foo", "a.txt"]).unwrap().print_config);
+        assert!(build(&["minigrep", "--no-print-config", "--print-config"]).unwrap().print_config);
+    }
+
+    #[test]
+    fn build_reads_output() {
+        assert_eq!(Output::Text, build(&["minigrep", "duct", "poem.txt"]).unwrap().output);
+        assert_eq!(Output::Json, build(&["minigrep", "--output", "json", "duct", "poem.txt"]).unwrap().output);
+        assert_eq!(
+            Some(ArgsError::InvalidValue("--output".to_string(), "xml".to_string())),
+            build(&["minigrep", "--output=xml", "duct", "poem.txt"]).err()
+        );
+    }
+
+    #[test]
+    fn build_errors() {
+        assert_eq!(Some(ArgsError::MissingQuery), build(&["minigrep"]).err());
+        assert_eq!(
+            Some(ArgsError::UnexpectedArgument("true".to_string())),
+            build(&["minigrep", "duct", "poem.txt", "true"]).err()
+        );
+        assert_eq!(Some(ArgsError::HelpRequested), build(&["minigrep", "--help"]).err());
+        assert_eq!(
+            Some(ArgsError::InvalidValue("--context".to_string(), "lots".to_string())),
+            build(&["minigrep", "-C", "lots", "duct", "poem.txt"]).err()
+        );
+    }
+}
@@ -1,29 +1,79 @@
 use std::env;
+use std::path::Path;
 use std::process;
 
+use minigrep::args::{self, ArgsError};
 use minigrep::Config;
 
 fn main() {
     // Get the arguments that were passed through command line.
     let args: Vec<String> = env::args().collect();
 
+    // "minigrep index build" isn't a search. "minigrep -- index build" still searches for "index" in the file "build".
+    if let [_, command, subcommand, rest @ ..] = &args[..] {
+        if command == "index" && subcommand == "build" {
+            process::exit(index_build(rest));
+        }
+    }
+
     // Implementing the parse_config function through config struct might be better, because Config struct is only used in context of parse_config.
     let config = Config::build(&args).unwrap_or_else(|err| {
+        // Asking for help isn't a mistake, so the usage is printed to stdout and the program exits successfully.
+        if err == ArgsError::HelpRequested {
+            print!("{}", args::usage());
+            process::exit(0);
+        }
+
         // Handling possible errors.
         // Note that the error is processed by the standard library error print macro.
         eprintln!("Problem parsing arguments: {err}");
+        eprintln!("Try 'minigrep --help' for more information.");
         // The process::exit function will stop the program immediately and return the number that was passed as the exit status code
-        process::exit(1);
+        // 2 is what grep uses for errors, 1 already means that nothing matched.
+        process::exit(2);
     });
 
+    if config.print_config {
+        print!("{}", config.describe());
+        process::exit(0);
+    }
+
     // Commented out, so the output.txt is more readable.
     // println!("In file {}", config.file_path);
     // println!("Searching for {}", config.query);
 
-    // Read the file.
-    if let Err(e) = minigrep::run(config) {
-        // Because error can be returned it needs to be handled.
-        println!("Application error: {e}");
-        process::exit(1);
+    // Read the file. The exit status follows grep: 0 when something matched, 1 when nothing did and 2 on errors.
+    match minigrep::run(config) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            // Because error can be returned it needs to be handled.
+            eprintln!("Application error: {e}");
+            process::exit(2);
+        }
+    }
+}
+
+// Builds or updates the index of the directory, the current directory when none is given.
+fn index_build(args: &[String]) -> i32 {
+    let dir = match args {
+        [] => ".",
+        [dir] => dir.as_str(),
+        [_, extra, ..] => {
+            eprintln!("Problem parsing arguments: {}", ArgsError::UnexpectedArgument(extra.clone()));
+            eprintln!("Usage: minigrep index build [DIR]");
+            return 2;
+        }
+    };
+
+    match minigrep::build_index(Path::new(dir)) {
+        Ok(stats) => {
+            println!("Indexed {} files ({} read, the rest unchanged) into {}", stats.files, stats.read, stats.path.display());
+            0
+        }
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            2
+        }
     }
-}
\ No newline at end of file
+}
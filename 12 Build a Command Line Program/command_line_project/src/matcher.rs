@@ -0,0 +1,323 @@
+use std::error::Error;
+use std::ops::Range;
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use regex::{Regex, RegexBuilder};
+
+use crate::expr::{self, Expr};
+use crate::{fold, folded_range, fuzzy, CaseFold, Config};
+
+// The search strategy picked from the config. It works on one line at a time,
+// so the input doesn't have to be read into memory before searching it.
+pub(crate) struct Matcher {
+    patterns: Patterns,
+    // With --expr the line has to make the expression true. Without it any of the patterns is enough.
+    expression: Option<Expression>,
+}
+
+// How the patterns are found in a line. Every kind can search for many patterns at once.
+enum Patterns {
+    // Aho-Corasick finds all the patterns in one pass over the line, so more patterns don't mean more passes.
+    Literal(AhoCorasick),
+    // The patterns are folded once when the matcher is built, the line is folded before it's searched.
+    CaseInsensitive(AhoCorasick, CaseFold),
+    Regex(Vec<Regex>),
+    // The patterns are split into characters once, and folded too when case is ignored.
+    Fuzzy { patterns: Vec<Vec<char>>, max_distance: usize, case_fold: Option<CaseFold> },
+}
+
+struct Expression {
+    expr: Expr,
+    // Which patterns are used without a NOT, only those are highlighted.
+    positive: Vec<bool>,
+}
+
+// A pattern that was found in a line. distance is only more than 0 for fuzzy matches.
+struct Found {
+    pattern: usize,
+    distance: usize,
+    columns: Range<usize>,
+}
+
+impl Matcher {
+    pub(crate) fn build(config: &Config) -> Result<Matcher, Box<dyn Error>> {
+        // Every -e (or the query) of an expression is parsed, and the expressions are joined with OR
+        // like separate patterns are. The patterns of all of them end up in the same list.
+        let (patterns, expression) = if config.expression {
+            let mut patterns = Vec::new();
+            let mut joined: Option<Expr> = None;
+
+            for query in &config.patterns {
+                let expr = expr::parse(query, &mut patterns)?;
+                joined = Some(match joined {
+                    Some(joined) => Expr::Or(Box::new(joined), Box::new(expr)),
+                    None => expr,
+                });
+            }
+
+            let expression = joined.map(|expr| {
+                let mut positive = vec![false; patterns.len()];
+                expr.mark_positive(false, &mut positive);
+                Expression { expr, positive }
+            });
+            (patterns, expression)
+        } else {
+            (config.patterns.clone(), None)
+        };
+
+        let patterns = if let Some(max_distance) = config.fuzzy {
+            let case_fold = config.ignore_case.then_some(config.case_fold);
+            let patterns = patterns
+                .iter()
+                .map(|pattern| match case_fold {
+                    Some(mode) => fold::fold(pattern, mode).chars().collect(),
+                    None => pattern.chars().collect(),
+                })
+                .collect();
+            Patterns::Fuzzy { patterns, max_distance, case_fold }
+        } else if config.regex {
+            let patterns = patterns
+                .iter()
+                .map(|pattern| RegexBuilder::new(pattern).case_insensitive(config.ignore_case).build())
+                .collect::<Result<_, _>>()?;
+            Patterns::Regex(patterns)
+        } else if config.ignore_case {
+            let folded: Vec<String> = patterns.iter().map(|pattern| fold::fold(pattern, config.case_fold)).collect();
+            Patterns::CaseInsensitive(automaton(&folded, expression.is_some())?, config.case_fold)
+        } else {
+            Patterns::Literal(automaton(&patterns, expression.is_some())?)
+        };
+
+        Ok(Matcher { patterns, expression })
+    }
+
+    pub(crate) fn is_fuzzy(&self) -> bool {
+        matches!(self.patterns, Patterns::Fuzzy { .. })
+    }
+
+    // Every match in the line, which is what gets highlighted. None means the line didn't match,
+    // an expression like "NOT a" can match a line without anything to highlight.
+    pub(crate) fn find_all(&self, line: &str) -> Option<Vec<Range<usize>>> {
+        self.find(line).map(|found| found.into_iter().map(|found| found.columns).collect())
+    }
+
+    // The distance of the closest pattern and what to highlight, for ranking fuzzy matches.
+    pub(crate) fn find_fuzzy(&self, line: &str) -> Option<(usize, Vec<Range<usize>>)> {
+        let found = self.find(line)?;
+        let distance = found.iter().map(|found| found.distance).min().unwrap_or(0);
+
+        Some((distance, found.into_iter().map(|found| found.columns).collect()))
+    }
+
+    fn find(&self, line: &str) -> Option<Vec<Found>> {
+        let found = self.patterns.find(line);
+
+        let Some(expression) = &self.expression else {
+            return if found.is_empty() { None } else { Some(without_overlaps(found)) };
+        };
+
+        // The overlaps are only dropped after this, "foo AND foobar" needs to know about both in "foobar".
+        let mut matched = vec![false; expression.positive.len()];
+        for found in &found {
+            matched[found.pattern] = true;
+        }
+        if !expression.expr.eval(&matched) {
+            return None;
+        }
+
+        let positive = found.into_iter().filter(|found| expression.positive[found.pattern]).collect();
+        Some(without_overlaps(positive))
+    }
+
+    // Replaces every match in the line and returns the new line with the ranges of the replaced parts in it.
+    // With a regex the replacement can use the groups of the match ("$1", "${name}"), otherwise it is used as it is.
+    pub(crate) fn replace(&self, line: &str, replacement: &str) -> (String, Vec<Range<usize>>) {
+        let mut replaced = String::with_capacity(line.len());
+        let mut ranges = Vec::new();
+        let mut copied = 0;
+
+        for found in self.find(line).unwrap_or_default() {
+            // Folding can make two matches end inside the same character, the second one is already replaced then.
+            if found.columns.start < copied {
+                continue;
+            }
+            replaced.push_str(&line[copied..found.columns.start]);
+            let start = replaced.len();
+
+            match &self.patterns {
+                // The match is found again with its groups, searching from where it starts finds the same match.
+                Patterns::Regex(regexes) => match regexes[found.pattern].captures_at(line, found.columns.start) {
+                    Some(captures) => captures.expand(replacement, &mut replaced),
+                    None => replaced.push_str(replacement),
+                },
+                _ => replaced.push_str(replacement),
+            }
+
+            ranges.push(start..replaced.len());
+            copied = found.columns.end;
+        }
+        replaced.push_str(&line[copied..]);
+
+        (replaced, ranges)
+    }
+}
+
+impl Patterns {
+    // Every pattern found in the line. The matches of different patterns can overlap each other.
+    fn find(&self, line: &str) -> Vec<Found> {
+        match self {
+            Patterns::Literal(automaton) => search(automaton, line)
+                .map(|found| Found { pattern: found.pattern().as_usize(), distance: 0, columns: found.range() })
+                .collect(),
+            Patterns::CaseInsensitive(automaton, mode) => {
+                let (folded, positions) = fold::fold_with_positions(line, *mode);
+                search(automaton, &folded)
+                    .map(|found| Found {
+                        pattern: found.pattern().as_usize(),
+                        distance: 0,
+                        columns: folded_range(line, &positions, found.start(), found.len()),
+                    })
+                    .collect()
+            }
+            Patterns::Regex(regexes) => regexes
+                .iter()
+                .enumerate()
+                .flat_map(|(pattern, regex)| {
+                    regex.find_iter(line).map(move |found| Found { pattern, distance: 0, columns: found.range() })
+                })
+                .collect(),
+            // Only the closest part of the line is a match for each pattern, the rest of it is probably close to nothing.
+            Patterns::Fuzzy { patterns, max_distance, case_fold } => {
+                let folded = case_fold.map(|mode| fold::fold_with_positions(line, mode));
+                let text = folded.as_ref().map_or(line, |(folded, _)| folded.as_str());
+
+                let found = patterns.iter().enumerate().filter_map(|(pattern, query)| {
+                    let (distance, columns) = fuzzy::find(query, text, *max_distance)?;
+                    let columns = match &folded {
+                        Some((_, positions)) => folded_range(line, positions, columns.start, columns.len()),
+                        None => columns,
+                    };
+                    Some(Found { pattern, distance, columns })
+                });
+                found.collect()
+            }
+        }
+    }
+}
+
+// Leftmost-longest skips the matches that overlap an earlier one, so a pattern that is part of another
+// one doesn't cut it short. An expression needs to know about every pattern in the line, even the overlapping ones,
+// and only the standard kind of automaton can report those.
+fn automaton<P: AsRef<[u8]>>(patterns: &[P], overlapping: bool) -> Result<AhoCorasick, aho_corasick::BuildError> {
+    let kind = if overlapping { MatchKind::Standard } else { MatchKind::LeftmostLongest };
+    AhoCorasick::builder().match_kind(kind).build(patterns)
+}
+
+fn search<'a>(automaton: &'a AhoCorasick, text: &'a str) -> Box<dyn Iterator<Item = aho_corasick::Match> + 'a> {
+    if automaton.match_kind() == MatchKind::Standard {
+        Box::new(automaton.find_overlapping_iter(text))
+    } else {
+        Box::new(automaton.find_iter(text))
+    }
+}
+
+// Overlapping matches are sorted and cut down the same way as leftmost-longest does it:
+// the match that starts first wins, and of the ones starting at the same place the longest.
+fn without_overlaps(mut found: Vec<Found>) -> Vec<Found> {
+    found.sort_by_key(|found| (found.columns.start, std::cmp::Reverse(found.columns.end)));
+
+    let mut kept: Vec<Found> = Vec::with_capacity(found.len());
+    for found in found {
+        match kept.last() {
+            Some(last) if found.columns.start < last.columns.end => {}
+            _ => kept.push(found),
+        }
+    }
+
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(args: &[&str]) -> Matcher {
+        let args: Vec<String> = ["minigrep"].iter().chain(args).map(|arg| arg.to_string()).collect();
+        Matcher::build(&Config::build_layered(&args, Vec::new()).unwrap()).unwrap()
+    }
+
+    // The matches as (start, end), a Vec with a single range in it looks too much like a mistake to clippy.
+    fn columns(matcher: &Matcher, line: &str) -> Option<Vec<(usize, usize)>> {
+        matcher.find_all(line).map(|found| found.iter().map(|columns| (columns.start, columns.end)).collect())
+    }
+
+    #[test]
+    fn many_literal_patterns() {
+        let matcher = matcher(&["-e", "safe", "-e", "fast", "-e", "fas", "-"]);
+
+        // "fas" is part of "fast", the longer one wins.
+        assert_eq!(Some(vec![0..4, 6..10]), matcher.find_all("safe, fast, productive."));
+        assert_eq!(None, matcher.find_all("Pick three."));
+    }
+
+    #[test]
+    fn many_case_insensitive_patterns() {
+        let matcher = matcher(&["-i", "-e", "STRASSE", "-e", "kiss", "-"]);
+
+        assert_eq!(Some(vec![0..4, 5..12]), matcher.find_all("Kiss Straße"));
+    }
+
+    #[test]
+    fn many_regex_patterns() {
+        let matcher = matcher(&["--regex", "-e", r"\d+", "-e", r"[a-z]+\d", "-"]);
+
+        // "abc1" starts before "1", so the digits inside it aren't a separate match.
+        assert_eq!(Some(vec![0..4, 5..7]), matcher.find_all("abc1 23"));
+    }
+
+    #[test]
+    fn expression() {
+        let matcher = matcher(&["--expr", "(error OR warning) AND NOT timeout", "-"]);
+
+        assert_eq!(Some(vec![(0, 5)]), columns(&matcher, "error: disk full"));
+        assert_eq!(Some(vec![(8, 15)]), columns(&matcher, "network warning"));
+        assert_eq!(None, matcher.find_all("error: timeout"));
+        assert_eq!(None, matcher.find_all("all good"));
+    }
+
+    #[test]
+    fn expression_of_overlapping_patterns() {
+        let matcher = matcher(&["--expr", "foo AND foobar", "-"]);
+
+        assert_eq!(Some(vec![(0, 6)]), columns(&matcher, "foobar"));
+        assert_eq!(None, matcher.find_all("foo bar"));
+    }
+
+    #[test]
+    fn only_negated_patterns_match_without_highlights() {
+        let matcher = matcher(&["--expr", "NOT timeout", "-"]);
+
+        assert_eq!(Some(vec![]), matcher.find_all("error: disk full"));
+        assert_eq!(None, matcher.find_all("error: timeout"));
+    }
+
+    #[test]
+    fn expressions_of_many_queries_are_joined_with_or() {
+        let matcher = matcher(&["--expr", "-e", "a AND b", "-e", "c", "-"]);
+
+        assert!(matcher.find_all("a b").is_some());
+        assert!(matcher.find_all("c").is_some());
+        assert!(matcher.find_all("a").is_none());
+    }
+
+    #[test]
+    fn replace_regex_of_many_patterns() {
+        let matcher = matcher(&["--regex", "-e", r"(\w+)@\w+", "-e", r"#(\d+)", "-"]);
+
+        // Every match uses the groups of the pattern that found it.
+        assert_eq!(
+            ("<ferris> fixed issue <42>".to_string(), vec![0..8, 21..25]),
+            matcher.replace("ferris@rust fixed issue #42", "<$1>")
+        );
+    }
+}
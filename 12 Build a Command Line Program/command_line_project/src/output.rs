@@ -0,0 +1,472 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::{Config, Line, Output};
+use crate::input::OwnedLine;
+
+// The same colors grep uses: magenta file names, green line numbers, cyan separators and bold red matches.
+const FILE_COLOR: &str = "\x1b[35m";
+const NUMBER_COLOR: &str = "\x1b[32m";
+const SEPARATOR_COLOR: &str = "\x1b[36m";
+const MATCH_COLOR: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[0m";
+
+// Prints the results of one file while it is being read. Lines are given to the printer one by one,
+// it remembers just enough of them to print the context before a match.
+pub struct Printer<'a, W: Write> {
+    config: &'a Config,
+    file: &'a Path,
+    // The text output only shows the file name when a directory is searched.
+    with_file_name: bool,
+    // Whether to use ANSI colors, run decides this from --color and the terminal.
+    colored: bool,
+    out: W,
+    // Lines that weren't printed yet, but could still be context for the next match.
+    before: VecDeque<OwnedLine>,
+    // How many lines after the latest match are still printed as context.
+    after_left: usize,
+    // Index of the first line that hasn't been printed yet, None until the first group is printed.
+    printed_until: Option<usize>,
+    count: usize,
+    // Binary lines aren't printed, a match in them only prints "Binary file X matches" once.
+    binary: bool,
+    binary_matched: bool,
+}
+
+impl<'a, W: Write> Printer<'a, W> {
+    pub fn new(config: &'a Config, file: &'a Path, with_file_name: bool, colored: bool, out: W) -> Printer<'a, W> {
+        Printer {
+            config,
+            file,
+            with_file_name,
+            colored,
+            out,
+            before: VecDeque::with_capacity(config.before_context),
+            after_left: 0,
+            printed_until: None,
+            count: 0,
+            binary: false,
+            binary_matched: false,
+        }
+    }
+
+    // Gives the next line to the printer. found is None when the line isn't selected, otherwise it has
+    // the parts of the line to highlight, which is nothing for the lines that -v selects.
+    pub fn line(&mut self, line: Line, found: Option<&[Range<usize>]>) -> io::Result<()> {
+        let found = match found {
+            Some(found) => found,
+            None => return self.context(line),
+        };
+        self.count += 1;
+
+        // Counting and listing files only need the number of matches, and JSON doesn't show context,
+        // so there is nothing else to keep track of.
+        if self.config.count || self.lists_files() {
+            return Ok(());
+        }
+
+        if self.binary {
+            return self.write_binary_match();
+        }
+        if self.config.output == Output::Json {
+            let mut found = line.matched(found.first().cloned().unwrap_or(0..0));
+            found.file = Some(self.file);
+            serde_json::to_writer(&mut self.out, &found)?;
+            return writeln!(self.out);
+        }
+
+        // The context before the match is printed first, matching lines are printed with ':' and context lines with '-'.
+        while let Some(context) = self.before.pop_front() {
+            self.write_line(&context.as_line(), '-', &[])?;
+        }
+        self.write_line(&line, ':', found)?;
+        self.after_left = self.config.after_context;
+
+        Ok(())
+    }
+
+    // Marks the rest of the input as binary. The context that was waiting isn't printed, it could be half of a binary line.
+    pub fn binary(&mut self) {
+        self.binary = true;
+        self.before.clear();
+        self.after_left = 0;
+    }
+
+    // True once -m matches have been found. The lines after that can still be context, but they aren't matches anymore.
+    pub fn is_full(&self) -> bool {
+        self.config.max_count.is_some_and(|max| self.count >= max)
+    }
+
+    // True once nothing more would be printed for this input, so the rest of it doesn't need to be read.
+    pub fn is_done(&self) -> bool {
+        if self.lists_files() {
+            return self.count > 0;
+        }
+        // A binary file only says that it matches, and that's said already.
+        if self.binary_matched {
+            return true;
+        }
+
+        self.is_full() && self.after_left == 0
+    }
+
+    fn lists_files(&self) -> bool {
+        self.config.files_with_matches || self.config.files_without_match
+    }
+
+    fn context(&mut self, line: Line) -> io::Result<()> {
+        if self.binary {
+            return Ok(());
+        }
+        if self.after_left > 0 {
+            self.after_left -= 1;
+            return self.write_line(&line, '-', &[]);
+        }
+
+        // Only the last few lines are kept, older ones can't be context for anything anymore.
+        if self.config.before_context > 0 {
+            if self.before.len() == self.config.before_context {
+                self.before.pop_front();
+            }
+            self.before.push_back(OwnedLine { index: line.index, offset: line.offset, text: line.text.to_string() });
+        }
+
+        Ok(())
+    }
+
+    // Whether the input read so far counts as a match for the exit status: -L matches the files it lists.
+    pub fn matched(&self) -> bool {
+        if self.lists_files() {
+            (self.count > 0) == self.config.files_with_matches
+        } else {
+            self.count > 0
+        }
+    }
+
+    // Prints the count or the file name, if that was asked for. The printer can't know the input ended without being told.
+    // Returns what matched returns.
+    pub fn finish(mut self) -> io::Result<bool> {
+        if self.lists_files() {
+            let listed = self.matched();
+            if listed {
+                self.write_listed_file()?;
+            }
+            return Ok(listed);
+        }
+
+        if self.config.count {
+            match self.config.output {
+                Output::Json => writeln!(self.out, "{}", json!({ "file": self.file.to_string_lossy(), "count": self.count }))?,
+                Output::Text => {
+                    if self.with_file_name {
+                        self.write_file_name(':')?;
+                    }
+                    writeln!(self.out, "{}", self.count)?;
+                }
+            }
+        }
+
+        Ok(self.matched())
+    }
+
+    fn write_binary_match(&mut self) -> io::Result<()> {
+        self.binary_matched = true;
+
+        match self.config.output {
+            Output::Json => writeln!(self.out, "{}", json!({ "file": self.file, "binary": true })),
+            Output::Text => writeln!(self.out, "Binary file {} matches", self.file.display()),
+        }
+    }
+
+    fn write_listed_file(&mut self) -> io::Result<()> {
+        match self.config.output {
+            Output::Json => writeln!(self.out, "{}", json!({ "file": self.file.to_string_lossy() })),
+            Output::Text => {
+                self.write_colored(FILE_COLOR, &self.file.display().to_string())?;
+                writeln!(self.out)
+            }
+        }
+    }
+
+    // Prints "file:line:offset:text", the parts that weren't asked for are left out.
+    fn write_line(&mut self, line: &Line, separator: char, found: &[Range<usize>]) -> io::Result<()> {
+        // Groups that aren't next to each other are separated with "--" like grep does.
+        if let Some(next) = self.printed_until {
+            let has_context = self.config.before_context > 0 || self.config.after_context > 0;
+            if has_context && line.index > next {
+                self.write_colored(SEPARATOR_COLOR, "--")?;
+                writeln!(self.out)?;
+            }
+        }
+        self.printed_until = Some(line.index + 1);
+
+        if self.with_file_name {
+            self.write_file_name(separator)?;
+        }
+        if self.config.line_number {
+            self.write_colored(NUMBER_COLOR, &(line.index + 1).to_string())?;
+            self.write_colored(SEPARATOR_COLOR, &separator.to_string())?;
+        }
+        if self.config.byte_offset {
+            self.write_colored(NUMBER_COLOR, &line.offset.to_string())?;
+            self.write_colored(SEPARATOR_COLOR, &separator.to_string())?;
+        }
+
+        if !self.colored {
+            return writeln!(self.out, "{}", line.text);
+        }
+
+        // The text between the matches is printed as it is, the matches are wrapped in color.
+        let mut printed = 0;
+        for columns in found.iter().filter(|columns| !columns.is_empty()) {
+            write!(self.out, "{}", &line.text[printed..columns.start])?;
+            self.write_colored(MATCH_COLOR, &line.text[columns.clone()])?;
+            printed = columns.end;
+        }
+        writeln!(self.out, "{}", &line.text[printed..])
+    }
+
+    fn write_file_name(&mut self, separator: char) -> io::Result<()> {
+        self.write_colored(FILE_COLOR, &self.file.display().to_string())?;
+        self.write_colored(SEPARATOR_COLOR, &separator.to_string())
+    }
+
+    fn write_colored(&mut self, color: &str, text: &str) -> io::Result<()> {
+        if self.colored {
+            write!(self.out, "{color}{text}{RESET}")
+        } else {
+            write!(self.out, "{text}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{search_reader, Matcher};
+
+    const POEM: &str = "\
+I'm nobody! Who are you?
+Are you nobody, too?
+Then there's a pair of us - don't tell!
+They'd banish us, you know.
+
+How dreary to be somebody!
+How public, like a frog
+To tell your name the livelong day
+To an admiring bog!";
+
+    // Searches the poem the same way run does with a file, and returns what was printed.
+    fn printed(args: &[&str], query: &str, with_file_name: bool) -> Vec<String> {
+        printed_colored(args, query, with_file_name, false)
+    }
+
+    fn printed_colored(args: &[&str], query: &str, with_file_name: bool, colored: bool) -> Vec<String> {
+        printed_input(POEM.as_bytes(), args, query, with_file_name, colored)
+    }
+
+    fn printed_input(input: &[u8], args: &[&str], query: &str, with_file_name: bool, colored: bool) -> Vec<String> {
+        let args: Vec<String> = ["minigrep"]
+            .iter()
+            .chain(args)
+            .chain(&[query, "poem.txt"])
+            .map(|arg| arg.to_string())
+            .collect();
+        let config = Config::build_layered(&args, Vec::new()).unwrap();
+        let matcher = Matcher::build(&config).unwrap();
+
+        let mut out = Vec::new();
+        search_reader(&config, &matcher, input, Path::new("poem.txt"), with_file_name, colored, &mut out).unwrap();
+
+        String::from_utf8(out).unwrap().lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn line_numbers_and_offsets() {
+        assert_eq!(
+            vec!["1:0:I'm nobody! Who are you?", "2:25:Are you nobody, too?"],
+            printed(&["-n", "-b"], "nobody", false)
+        );
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            vec!["poem.txt:3:Then there's a pair of us - don't tell!", "poem.txt:8:To tell your name the livelong day"],
+            printed(&["-n"], "tell", true)
+        );
+    }
+
+    #[test]
+    fn context_groups_are_separated() {
+        assert_eq!(
+            vec![
+                "2-Are you nobody, too?",
+                "3:Then there's a pair of us - don't tell!",
+                "4-They'd banish us, you know.",
+                "--",
+                "7-How public, like a frog",
+                "8:To tell your name the livelong day",
+                "9-To an admiring bog!",
+            ],
+            printed(&["-n", "-C1"], "tell", false)
+        );
+    }
+
+    #[test]
+    fn overlapping_context_is_merged() {
+        assert_eq!(
+            vec![
+                "They'd banish us, you know.",
+                "",
+                "How dreary to be somebody!",
+                "How public, like a frog",
+            ],
+            printed(&["-B2"], "How", false)
+        );
+    }
+
+    #[test]
+    fn after_context_continues_through_matches() {
+        assert_eq!(
+            vec![
+                "1:I'm nobody! Who are you?",
+                "2:Are you nobody, too?",
+                "3-Then there's a pair of us - don't tell!",
+                "4:They'd banish us, you know.",
+                "5-",
+                "--",
+                "8:To tell your name the livelong day",
+                "9-To an admiring bog!",
+            ],
+            printed(&["-n", "-A1"], "you", false)
+        );
+    }
+
+    #[test]
+    fn count_with_file() {
+        assert_eq!(vec!["poem.txt:4"], printed(&["-c"], "you", true));
+        assert_eq!(vec!["4"], printed(&["-c"], "you", false));
+    }
+
+    #[test]
+    fn json_lines() {
+        assert_eq!(
+            vec![r#"{"file":"poem.txt","line_number":7,"offset":142,"columns":{"start":4,"end":10},"text":"How public, like a frog"}"#],
+            printed(&["--output", "json"], "public", false)
+        );
+    }
+
+    #[test]
+    fn json_count() {
+        assert_eq!(vec![r#"{"count":2,"file":"poem.txt"}"#], printed(&["-c", "--output", "json"], "tell", false));
+    }
+
+    #[test]
+    fn highlights_every_match() {
+        let printed = printed_colored(&[], "o", false, true);
+
+        assert_eq!(
+            "T\x1b[1;31mo\x1b[0m an admiring b\x1b[1;31mo\x1b[0mg!",
+            printed.last().unwrap()
+        );
+    }
+
+    #[test]
+    fn highlights_case_insensitive_matches() {
+        assert_eq!(
+            vec!["\x1b[1;31mHow\x1b[0m dreary to be somebody!", "\x1b[1;31mHow\x1b[0m public, like a frog"],
+            printed_colored(&["-i"], "hOW", false, true)
+        );
+    }
+
+    #[test]
+    fn colors_file_names_and_line_numbers() {
+        assert_eq!(
+            vec!["\x1b[35mpoem.txt\x1b[0m\x1b[36m:\x1b[0m\x1b[32m7\x1b[0m\x1b[36m:\x1b[0mHow public, like a \x1b[1;31mfrog\x1b[0m"],
+            printed_colored(&["-n"], "frog", true, true)
+        );
+    }
+
+    #[test]
+    fn invert_match() {
+        assert_eq!(
+            vec![
+                "3:Then there's a pair of us - don't tell!",
+                "5:",
+                "6:How dreary to be somebody!",
+                "7:How public, like a frog",
+                "9:To an admiring bog!",
+            ],
+            printed(&["-n", "-v"], "you", false)
+        );
+        assert_eq!(vec!["5"], printed(&["-v", "-c"], "you", false));
+    }
+
+    #[test]
+    fn max_count_keeps_the_trailing_context() {
+        assert_eq!(
+            vec!["1:I'm nobody! Who are you?", "2:Are you nobody, too?", "3-Then there's a pair of us - don't tell!"],
+            printed(&["-n", "-m2", "-A1"], "you", false)
+        );
+        assert_eq!(vec!["2"], printed(&["-m", "2", "-c"], "you", false));
+        assert!(printed(&["-m0"], "you", false).is_empty());
+    }
+
+    #[test]
+    fn list_files() {
+        assert_eq!(vec!["poem.txt"], printed(&["-l"], "frog", false));
+        assert!(printed(&["-l"], "toad", false).is_empty());
+        assert_eq!(vec!["poem.txt"], printed(&["-L"], "toad", false));
+        assert!(printed(&["-L"], "frog", false).is_empty());
+        // Listing wins over counting, like in grep.
+        assert_eq!(vec![r#"{"file":"poem.txt"}"#], printed(&["-l", "-c", "--output", "json"], "frog", false));
+    }
+
+    #[test]
+    fn replaced_lines() {
+        assert_eq!(
+            vec!["1:I'm somebody! Who are you?", "2:Are you somebody, too?"],
+            printed(&["-n", "--replace", "somebody"], "nobody", false)
+        );
+        assert_eq!(
+            vec!["How public, like a \x1b[1;31mtoad\x1b[0m"],
+            printed_colored(&["--replace", "toad"], "frog", false, true)
+        );
+    }
+
+    #[test]
+    fn fuzzy_lines_are_ranked() {
+        assert_eq!(
+            vec!["9:To an admiring bog!", "7:How public, like a frog"],
+            printed(&["-n", "--fuzzy", "2"], "a bog", false)
+        );
+        assert_eq!(vec!["9:To an admiring bog!"], printed(&["-n", "--fuzzy=2", "-m1"], "a bog", false));
+    }
+
+    #[test]
+    fn binary_files_only_say_they_match() {
+        let binary = b"\x7fELF\x02\x01\x00\x00\nneedle\nneedle again\n";
+
+        assert_eq!(vec!["Binary file poem.txt matches"], printed_input(binary, &["-n", "-C1"], "needle", false, false));
+        assert_eq!(vec![r#"{"binary":true,"file":"poem.txt"}"#], printed_input(binary, &["--output", "json"], "needle", false, false));
+        // Counting still counts every matching line.
+        assert_eq!(vec!["2"], printed_input(binary, &["-c"], "needle", false, false));
+        assert!(printed_input(binary, &[], "haystack", false, false).is_empty());
+    }
+
+    #[test]
+    fn invalid_utf8_makes_the_rest_binary() {
+        let input = b"needle one\ncaf\xe9\nneedle two\n";
+
+        assert_eq!(vec!["needle one", "Binary file poem.txt matches"], printed_input(input, &[], "needle", false, false));
+        assert_eq!(
+            vec!["1:needle one", "2-caf\u{fffd}", "3:needle two"],
+            printed_input(input, &["--lossy", "-n", "-C1"], "needle", false, false)
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex, PoisonError},
+    thread
+};
+
+// The same thread pool as the one in the web server of chapter 20, without the prints.
+// minigrep can't depend on that crate, so the pool lives here. Unlike the book's pool a panicking job
+// doesn't take its worker down with it, and a thread that can't be started is an error instead of a panic.
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: Option<mpsc::Sender<Job>>
+}
+
+impl ThreadPool {
+    /// Create a new ThreadPool.
+    ///
+    /// The size is the number of threads in the pool.
+    ///
+    /// # Panics
+    ///
+    /// The `new` function will panic if the size is zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the operating system when a thread can't be started.
+    pub fn new(size: usize) -> io::Result<ThreadPool> {
+        assert!(size > 0);
+
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut workers = Vec::with_capacity(size);
+
+        for id in 0..size {
+            workers.push(Worker::new(id, Arc::clone(&receiver))?);
+        }
+
+        Ok(ThreadPool { workers, sender: Some(sender) })
+    }
+
+    pub fn execute<F>(&self, f: F)
+        where F: FnOnce() + Send + 'static,
+        {
+            let job = Box::new(f);
+
+            self.sender.as_ref().unwrap().send(job).unwrap();
+        }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Closing the channel lets the workers finish the jobs that are left and then stop.
+        drop(self.sender.take());
+
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                // The jobs are run under catch_unwind, so a worker can't have panicked. Even if it did, there's nothing to clean up.
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+struct Worker {
+    thread: Option<thread::JoinHandle<()>>
+}
+
+impl Worker {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> io::Result<Worker> {
+        let thread = thread::Builder::new()
+            .name(format!("minigrep-worker-{id}"))
+            .spawn(move || loop {
+                // No job panics while the lock is held, but a poisoned lock would stop every worker, so it's ignored.
+                let message = receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+
+                match message {
+                    // The panic message was already printed by the panic hook.
+                    Ok(job) => {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                    }
+                    Err(_) => break,
+                }
+            })?;
+
+        Ok(Worker { thread: Some(thread) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panicking_job_keeps_the_worker() {
+        let pool = ThreadPool::new(1).unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        pool.execute(|| panic!("broken job"));
+        pool.execute(move || sender.send(42).unwrap());
+
+        assert_eq!(Ok(42), receiver.recv_timeout(std::time::Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,112 @@
+use memchr::memmem::Finder;
+use memchr::{memchr, memchr_iter, memrchr};
+
+use crate::{lines, Line, Match};
+
+// Finds the lines that contain the query without splitting the contents into lines first.
+// memmem looks for the query in the whole buffer at once (with SIMD where the CPU has it, and the Two-Way
+// algorithm otherwise), and only the line around a hit is looked for. Most lines of a big input don't match,
+// and their line endings are only ever counted, never split on.
+// The results are the same as searching line by line: the first match of every line that has one.
+pub fn search<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    // A line never has a line ending in it, so a query with one can't match.
+    if query.contains('\n') {
+        return Vec::new();
+    }
+    // Every line contains the empty query, there is nothing to scan for.
+    if query.is_empty() {
+        return lines(contents).map(|line| line.matched(0..0)).collect();
+    }
+
+    let bytes = contents.as_bytes();
+    let finder = Finder::new(query);
+    let mut results = Vec::new();
+    let mut position = 0;
+    // The line endings are counted up to the latest matching line, so the lines in between are never split.
+    let mut index = 0;
+    let mut counted = 0;
+
+    while let Some(found) = finder.find(&bytes[position..]) {
+        let start = position + found;
+        let line_start = memrchr(b'\n', &bytes[..start]).map_or(0, |newline| newline + 1);
+        let line_end = memchr(b'\n', &bytes[start..]).map_or(bytes.len(), |newline| start + newline);
+        // "\r\n" is a line ending too, but a "\r" on the last line without a "\n" is text like str::lines does it.
+        let text_end = if line_end < bytes.len() && line_end > line_start && bytes[line_end - 1] == b'\r' {
+            line_end - 1
+        } else {
+            line_end
+        };
+
+        // The match ran into the "\r" of the line ending, the query can still be later in the same line.
+        if start + query.len() > text_end {
+            position = start + 1;
+            continue;
+        }
+
+        index += memchr_iter(b'\n', &bytes[counted..line_start]).count();
+        counted = line_start;
+
+        let line = Line { index, offset: line_start, text: &contents[line_start..text_end] };
+        let column = start - line_start;
+        results.push(line.matched(column..column + query.len()));
+
+        // Only the first match of a line is reported, so the search goes on from the next line.
+        if line_end == bytes.len() {
+            break;
+        }
+        position = line_end + 1;
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search_by_line;
+
+    // The scan has to find exactly what looking at every line finds.
+    fn same_as_by_line(query: &str, contents: &str) {
+        assert_eq!(search_by_line(query, contents), search(query, contents), "query {query:?} in {contents:?}");
+    }
+
+    #[test]
+    fn finds_the_same_lines() {
+        let contents = "Rust:\nsafe, fast, productive.\nPick three.\nDuct tape.";
+
+        assert_eq!(1, search("duct", contents).len());
+        for query in ["duct", "e", "Pick three.", "Rust:", "tape.", "t", "nothing", "Duct tape.\n"] {
+            same_as_by_line(query, contents);
+        }
+    }
+
+    #[test]
+    fn line_endings() {
+        let contents = "one\r\ntwo\r\n\r\nthree\r";
+
+        for query in ["one", "o", "\r", "e\r", "three\r", "two\r\n"] {
+            same_as_by_line(query, contents);
+        }
+        // The last line doesn't end with "\r\n", so its "\r" is part of the text.
+        assert_eq!(4, search("e\r", contents)[0].line_number);
+    }
+
+    #[test]
+    fn empty_query_and_contents() {
+        same_as_by_line("", "one\ntwo\n");
+        same_as_by_line("", "");
+        same_as_by_line("one", "");
+        same_as_by_line("", "\n\n");
+    }
+
+    #[test]
+    fn offsets_and_unicode() {
+        let contents = "straße\nStraße und Straße\n東京\n";
+
+        for query in ["ß", "Straße", "京", "e u"] {
+            same_as_by_line(query, contents);
+        }
+        let found = &search("Straße", contents)[0];
+        assert_eq!((8, 0..7), (found.offset, found.columns.clone()));
+    }
+}
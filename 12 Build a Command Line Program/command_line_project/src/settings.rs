@@ -0,0 +1,310 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::args::{ArgsError, Opt, FLAGS};
+use crate::{CaseFold, Color, Config, Output};
+
+// Every option can also be set in a config file or an environment variable. They are read in layers,
+// and a later layer overwrites what an earlier one set:
+//
+//   defaults < ~/.config/minigrep/config.toml < .minigreprc < environment < command line
+//
+// The files are TOML and use the long flag names as keys ("ignore-case = true", "context = 2",
+// "include = ["*.rs"]"). The project file is the .minigreprc in the current directory or the closest parent directory.
+// The environment variables are the flag names in capitals with a MINIGREP_ prefix (MINIGREP_IGNORE_CASE=1).
+// Lists like include are added together instead of being overwritten.
+
+// These only make sense on the command line: -e, -f and --interactive change what the positional arguments mean.
+// The editing flags rewrite files, so a .minigreprc in a checked out repository or a leftover variable
+// must not be able to turn a plain search into editing every file that matches.
+const COMMAND_LINE_ONLY: &[&str] =
+    &["help", "print-config", "regexp", "file", "interactive", "replace", "in-place", "backup", "dry-run"];
+
+// The config file of the user, $XDG_CONFIG_HOME is used when it's set like most Linux programs do.
+const USER_FILE: &str = "minigrep/config.toml";
+const PROJECT_FILE: &str = ".minigreprc";
+const ENV_PREFIX: &str = "MINIGREP_";
+
+// Where a value came from, --print-config shows this next to every value.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Source {
+    Default,
+    UserFile(PathBuf),
+    ProjectFile(PathBuf),
+    Env(String),
+    CommandLine,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::UserFile(path) => write!(f, "user config {}", path.display()),
+            Source::ProjectFile(path) => write!(f, "project config {}", path.display()),
+            Source::Env(name) => write!(f, "environment {name}"),
+            Source::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+// The options one source set, in the same form the command line parser gives them.
+#[derive(Debug, PartialEq)]
+pub struct Layer {
+    pub source: Source,
+    pub opts: Vec<Opt>,
+}
+
+// Reads the config files and the environment, lowest precedence first. A missing file is just an empty layer.
+pub fn load() -> Result<Vec<Layer>, ArgsError> {
+    let mut layers = Vec::new();
+
+    if let Some(path) = user_file() {
+        if let Some(layer) = read_file(&path, Source::UserFile(path.clone()))? {
+            layers.push(layer);
+        }
+    }
+    if let Some(path) = env::current_dir().ok().and_then(|dir| project_file(&dir)) {
+        if let Some(layer) = read_file(&path, Source::ProjectFile(path.clone()))? {
+            layers.push(layer);
+        }
+    }
+    layers.extend(env_layers(|name| env::var(name).ok()));
+
+    Ok(layers)
+}
+
+fn user_file() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_dir.join(USER_FILE))
+}
+
+// The closest .minigreprc, so running minigrep in a subdirectory of a project still finds it.
+fn project_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().map(|dir| dir.join(PROJECT_FILE)).find(|path| path.is_file())
+}
+
+fn read_file(path: &Path, source: Source) -> Result<Option<Layer>, ArgsError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) if !path.exists() => return Ok(None),
+        Err(e) => return Err(ArgsError::InvalidConfig(source.to_string(), e.to_string())),
+    };
+
+    parse_file(&contents, source).map(Some)
+}
+
+// Turns the TOML table into options. The values are checked later by Config::build, the same way as on the command line.
+pub fn parse_file(contents: &str, source: Source) -> Result<Layer, ArgsError> {
+    let invalid = |message: String| ArgsError::InvalidConfig(source.to_string(), message);
+    let table: toml::Table = contents.parse().map_err(|e: toml::de::Error| invalid(e.message().to_string()))?;
+    let mut opts = Vec::new();
+
+    for (key, value) in table {
+        let name = configurable(&key).ok_or_else(|| invalid(format!("unknown option '{key}'")))?;
+
+        let values = match value {
+            // A list sets the option many times, which only makes sense for options like include.
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                _ => return Err(invalid(format!("'{key}' has to be a string, a number or true/false"))),
+            };
+            opts.push(Opt { name, value: Some(value) });
+        }
+    }
+
+    Ok(Layer { source, opts })
+}
+
+// One layer per variable, so every value knows which variable it came from.
+fn env_layers(var: impl Fn(&str) -> Option<String>) -> Vec<Layer> {
+    let mut layers = Vec::new();
+
+    // The old way of turning on -i. It turns it on whatever the value is, like it always did.
+    if var("IGNORE_CASE").is_some() {
+        layers.push(Layer {
+            source: Source::Env(String::from("IGNORE_CASE")),
+            opts: vec![Opt { name: "ignore-case", value: None }],
+        });
+    }
+
+    for flag in FLAGS.iter().filter(|flag| !COMMAND_LINE_ONLY.contains(&flag.long)) {
+        let name = format!("{ENV_PREFIX}{}", flag.long.to_uppercase().replace('-', "_"));
+        let Some(value) = var(&name) else {
+            continue;
+        };
+
+        // Switches are on unless the variable says otherwise, MINIGREP_COUNT=1 and MINIGREP_COUNT=yes both work.
+        let value = if flag.value.is_none() && matches!(value.as_str(), "0" | "false" | "no" | "off") {
+            String::from("false")
+        } else if flag.value.is_none() {
+            String::from("true")
+        } else {
+            value
+        };
+        layers.push(Layer { source: Source::Env(name), opts: vec![Opt { name: flag.long, value: Some(value) }] });
+    }
+
+    layers
+}
+
+fn configurable(key: &str) -> Option<&'static str> {
+    FLAGS
+        .iter()
+        .map(|flag| flag.long)
+        .find(|name| *name == key && !COMMAND_LINE_ONLY.contains(name))
+}
+
+// The effective configuration as TOML, so it can be copied into a config file. Every value has its source next to it.
+pub fn describe(config: &Config) -> String {
+    let quoted = |value: &str| format!("{value:?}");
+    let list = |values: &[String]| format!("[{}]", values.iter().map(|value| quoted(value)).collect::<Vec<_>>().join(", "));
+
+    let values: Vec<(&str, Option<String>)> = vec![
+        ("ignore-case", Some(config.ignore_case.to_string())),
+        ("case-fold", Some(quoted(match config.case_fold {
+            CaseFold::Unicode => "unicode",
+            CaseFold::Lowercase => "lowercase",
+        }))),
+        ("line-number", Some(config.line_number.to_string())),
+        ("byte-offset", Some(config.byte_offset.to_string())),
+        ("after-context", Some(config.after_context.to_string())),
+        ("before-context", Some(config.before_context.to_string())),
+        ("count", Some(config.count.to_string())),
+        ("files-with-matches", Some(config.files_with_matches.to_string())),
+        ("files-without-match", Some(config.files_without_match.to_string())),
+        ("invert-match", Some(config.invert.to_string())),
+        ("max-count", config.max_count.map(|max| max.to_string())),
+        ("output", Some(quoted(match config.output {
+            Output::Text => "text",
+            Output::Json => "json",
+        }))),
+        ("color", Some(quoted(match config.color {
+            Color::Auto => "auto",
+            Color::Always => "always",
+            Color::Never => "never",
+        }))),
+        ("regex", Some(config.regex.to_string())),
+        ("expr", Some(config.expression.to_string())),
+        ("fuzzy", config.fuzzy.map(|distance| distance.to_string())),
+        ("jobs", Some(config.jobs.to_string())),
+        ("include", Some(list(&config.include))),
+        ("exclude", Some(list(&config.exclude))),
+        ("search-zip", Some(config.search_zip.to_string())),
+        ("encoding", Some(quoted(config.encoding.map_or("auto", |encoding| encoding.name())))),
+        ("lossy", Some(config.lossy.to_string())),
+        ("hidden", Some(config.hidden.to_string())),
+        ("no-ignore", Some(config.no_ignore.to_string())),
+        ("index", Some(config.index.to_string())),
+    ];
+
+    let mut described = String::from("# Later sources win: command line > environment > .minigreprc > config.toml > defaults\n");
+    for (name, value) in values {
+        let source = config.sources.get(name).unwrap_or(&Source::Default);
+        // TOML has no empty value, options that aren't set are commented out.
+        let line = match value {
+            Some(value) => format!("{name} = {value}"),
+            None => format!("# {name} isn't set"),
+        };
+        described.push_str(&format!("{line:<32} # {source}\n"));
+    }
+
+    described
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::collections::HashMap;
+
+    fn opt(name: &'static str, value: &str) -> Opt {
+        Opt { name, value: Some(value.to_string()) }
+    }
+
+    #[test]
+    fn parses_config_files() {
+        let layer = parse_file(
+            "ignore-case = true\ncontext = 2\ncolor = \"never\"\ninclude = [\"*.rs\", \"*.toml\"]\n",
+            Source::ProjectFile(PathBuf::from(".minigreprc")),
+        )
+        .unwrap();
+
+        // The table is sorted by key.
+        assert_eq!(
+            vec![
+                opt("color", "never"),
+                opt("context", "2"),
+                opt("ignore-case", "true"),
+                opt("include", "*.rs"),
+                opt("include", "*.toml"),
+            ],
+            layer.opts
+        );
+    }
+
+    #[test]
+    fn config_file_errors() {
+        let source = || Source::UserFile(PathBuf::from("config.toml"));
+
+        assert_eq!(
+            Err(ArgsError::InvalidConfig(
+                String::from("user config config.toml"),
+                String::from("unknown option 'colour'")
+            )),
+            parse_file("colour = \"never\"", source())
+        );
+        // -e changes what the arguments mean, so it can't hide in a file.
+        assert!(parse_file("regexp = \"fn\"", source()).is_err());
+        // Neither can the editing flags, a file in a repository shouldn't be able to rewrite it.
+        assert!(parse_file("in-place = true", source()).is_err());
+        assert!(parse_file("replace = \"x\"", source()).is_err());
+        assert!(parse_file("context = 1.5", source()).is_err());
+        assert!(parse_file("context = ", source()).is_err());
+    }
+
+    #[test]
+    fn environment_variables() {
+        let vars: HashMap<&str, &str> =
+            [
+                ("IGNORE_CASE", "0"),
+                ("MINIGREP_LINE_NUMBER", "0"),
+                ("MINIGREP_COUNT", "yes"),
+                ("MINIGREP_CONTEXT", "3"),
+                ("MINIGREP_IN_PLACE", "1"),
+                ("MINIGREP_REPLACE", "x"),
+            ]
+            .into();
+        let layers = env_layers(|name| vars.get(name).map(|value| value.to_string()));
+
+        assert_eq!(
+            vec![
+                (Source::Env(String::from("IGNORE_CASE")), Opt { name: "ignore-case", value: None }),
+                (Source::Env(String::from("MINIGREP_LINE_NUMBER")), opt("line-number", "false")),
+                (Source::Env(String::from("MINIGREP_CONTEXT")), opt("context", "3")),
+                (Source::Env(String::from("MINIGREP_COUNT")), opt("count", "true")),
+            ],
+            layers.into_iter().flat_map(|layer| layer.opts.into_iter().map(move |opt| (layer.source.clone(), opt))).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn project_file_is_found_in_a_parent() {
+        let root = TempDir::new("settings");
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::write(root.join(PROJECT_FILE), "count = true").unwrap();
+
+        assert_eq!(Some(root.join(PROJECT_FILE)), project_file(&root.join("src/nested")));
+    }
+}
@@ -0,0 +1,552 @@
+use std::env;
+use std::error::Error;
+use std::io::{self, IsTerminal};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line as TextLine, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::input::{self, OwnedLine};
+use crate::{select, walk, walk_filter, Config, Matcher};
+
+// Every keystroke searches everything again, so only this many hits are kept to stay responsive.
+const MAX_HITS: usize = 10_000;
+
+// How often the screen checks if the search is done while one is running.
+const SEARCH_POLL: Duration = Duration::from_millis(20);
+
+const HELP: &str = "Tab: toggle case · ↑↓ PgUp PgDn: scroll · Enter: open in $EDITOR · Esc: quit";
+
+// A file that was read into memory when the interactive mode started. Reading the files again
+// for every keystroke would be much slower than searching the lines that are already there.
+struct Loaded {
+    path: PathBuf,
+    lines: Vec<OwnedLine>,
+}
+
+// One matching line, pointing into the loaded files.
+struct Hit {
+    file: usize,
+    line: usize,
+    found: Vec<Range<usize>>,
+}
+
+// What a search found. It's sent back from the search thread.
+struct Found {
+    hits: Vec<Hit>,
+    truncated: bool,
+}
+
+// A search running on its own thread, so typing doesn't have to wait for it to finish.
+struct Search {
+    results: mpsc::Receiver<Found>,
+    // Set when the query changes before the search is done, the thread stops at the next line.
+    cancelled: Arc<AtomicBool>,
+}
+
+struct App {
+    // The query is typed into config.patterns, so the search works the same as on the command line.
+    config: Config,
+    query: String,
+    // Each file is shared with the search threads. A file read again after editing it is replaced as a whole.
+    files: Vec<Arc<Loaded>>,
+    // The results of the last finished search, they stay on the screen until the next one is done.
+    hits: Vec<Hit>,
+    // MAX_HITS lines matched, there can be more that aren't shown.
+    truncated: bool,
+    list: ListState,
+    // Shown in the status line instead of the help, an unfinished regex is an error until it's finished.
+    error: Option<String>,
+    // How many results fit on the screen, PgUp and PgDn move this much.
+    page: usize,
+    searching: Option<Search>,
+}
+
+// Runs the terminal UI until it's closed. Returns whether the last query matched something, for the exit status.
+pub fn run(config: Config) -> Result<bool, Box<dyn Error>> {
+    if config.file_path == input::STDIN {
+        return Err("--interactive needs a file or a directory to search, not standard input".into());
+    }
+    if !io::stdout().is_terminal() {
+        return Err("--interactive needs a terminal".into());
+    }
+
+    let files = load(&config)?;
+    let mut app = App::new(config, files);
+
+    // init puts the terminal into raw mode and the alternate screen, and restores it on a panic too.
+    let mut terminal = ratatui::init();
+    let result = app.event_loop(&mut terminal);
+    ratatui::restore();
+
+    result.map(|_| !app.hits.is_empty())
+}
+
+// Reads every file that would be searched. Binary files are left out, they couldn't be shown anyway.
+fn load(config: &Config) -> Result<Vec<Arc<Loaded>>, Box<dyn Error>> {
+    let path = Path::new(&config.file_path);
+    let paths = if path.is_dir() {
+        walk::files(path, &walk_filter(config)?).files
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut files = Vec::new();
+    for path in paths {
+        // A file that can't be read doesn't stop the others from being searched.
+        if let Ok(Some(lines)) = read_file(config, &path) {
+            files.push(Arc::new(Loaded { path, lines }));
+        }
+    }
+
+    Ok(files)
+}
+
+fn read_file(config: &Config, path: &Path) -> io::Result<Option<Vec<OwnedLine>>> {
+    let mut reader = input::open(path, config.search_zip, config.encoding)?;
+    if input::is_binary(&mut reader)? {
+        return Ok(None);
+    }
+
+    let mut lines = input::read_lines(reader).lossy();
+    let mut read = Vec::new();
+    for line in lines.by_ref() {
+        let line = line?;
+        if line.text.contains('\0') {
+            return Ok(None);
+        }
+        read.push(line);
+    }
+
+    if lines.found_invalid() && !config.lossy {
+        return Ok(None);
+    }
+    Ok(Some(read))
+}
+
+impl App {
+    fn new(config: Config, files: Vec<Arc<Loaded>>) -> App {
+        // The first pattern from -e is where the query starts.
+        let query = config.patterns.first().cloned().unwrap_or_default();
+        let mut app = App {
+            config,
+            query,
+            files,
+            hits: Vec::new(),
+            truncated: false,
+            list: ListState::default(),
+            error: None,
+            page: 10,
+            searching: None,
+        };
+        app.search();
+        app
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn Error>> {
+        loop {
+            self.receive();
+            terminal.draw(|frame| self.draw(frame))?;
+
+            // While a search is running the screen is drawn again once it's done, even without a key being pressed.
+            if self.searching.is_some() && !event::poll(SEARCH_POLL)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            // Windows also sends an event when a key is released, only the presses count.
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                KeyCode::Enter => self.open_selected(terminal)?,
+                _ => self.key(key),
+            }
+        }
+    }
+
+    // Everything but quitting and opening the editor, those two need the terminal.
+    fn key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Tab => {
+                self.config.ignore_case = !self.config.ignore_case;
+                self.search();
+            }
+            KeyCode::Up => self.list.select_previous(),
+            KeyCode::Down => self.list.select_next(),
+            KeyCode::PageUp => self.list.scroll_up_by(self.page as u16),
+            KeyCode::PageDown => self.list.scroll_down_by(self.page as u16),
+            KeyCode::Home => self.list.select_first(),
+            KeyCode::End => self.list.select_last(),
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.search();
+            }
+            // Ctrl-U clears the line like it does in a shell.
+            KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.query.clear();
+                self.search();
+            }
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.query.push(c);
+                self.search();
+            }
+            _ => {}
+        }
+    }
+
+    // Starts searching for the query on another thread. A search for an older query that is still running
+    // is cancelled, its results wouldn't be shown anyway.
+    fn search(&mut self) {
+        if let Some(search) = self.searching.take() {
+            search.cancelled.store(true, Ordering::Relaxed);
+        }
+        self.error = None;
+
+        // An empty query would match every line, which isn't interesting to look at.
+        if self.query.is_empty() {
+            self.show(Found { hits: Vec::new(), truncated: false });
+            return;
+        }
+        self.config.patterns = vec![self.query.clone()];
+        let matcher = match Matcher::build(&self.config) {
+            Ok(matcher) => matcher,
+            Err(e) => {
+                self.error = Some(e.to_string());
+                self.show(Found { hits: Vec::new(), truncated: false });
+                return;
+            }
+        };
+
+        let (sender, results) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let config = self.config.clone();
+        let files = self.files.clone();
+        let stop = Arc::clone(&cancelled);
+        thread::spawn(move || {
+            if let Some(found) = find(&config, &matcher, &files, &stop) {
+                // The app doesn't wait for this search anymore when sending fails, that's fine.
+                let _ = sender.send(found);
+            }
+        });
+        self.searching = Some(Search { results, cancelled });
+    }
+
+    // Shows the results of the running search, if it's done.
+    fn receive(&mut self) {
+        let Some(search) = &self.searching else {
+            return;
+        };
+        match search.results.try_recv() {
+            Ok(found) => {
+                self.searching = None;
+                self.show(found);
+            }
+            Err(TryRecvError::Empty) => {}
+            // The search thread is gone without a result, it panicked. The old results stay.
+            Err(TryRecvError::Disconnected) => self.searching = None,
+        }
+    }
+
+    // Waits for the running search to finish, for when the results are needed right away.
+    fn wait(&mut self) {
+        if let Some(search) = self.searching.take() {
+            if let Ok(found) = search.results.recv() {
+                self.show(found);
+            }
+        }
+    }
+
+    fn show(&mut self, found: Found) {
+        self.hits = found.hits;
+        self.truncated = found.truncated;
+        self.list.select(if self.hits.is_empty() { None } else { Some(0) });
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [query_area, results_area, status_area] =
+            Layout::vertical([Constraint::Length(3), Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        self.page = usize::from(results_area.height.max(1));
+
+        let case = if self.config.ignore_case { "ignoring case" } else { "matching case" };
+        let query = Paragraph::new(self.query.as_str()).block(Block::bordered().title(format!(" minigrep, {case} ")));
+        frame.render_widget(query, query_area);
+        let cursor = query_area.x + 1 + Span::raw(self.query.as_str()).width() as u16;
+        frame.set_cursor_position((cursor.min(query_area.right().saturating_sub(2)), query_area.y + 1));
+
+        let items: Vec<ListItem> = self.hits.iter().map(|hit| ListItem::new(self.hit_line(hit))).collect();
+        let list = List::new(items).highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, results_area, &mut self.list);
+
+        let status = match &self.error {
+            Some(error) => TextLine::styled(error.as_str(), Style::new().fg(Color::Red)),
+            None => {
+                let files = self.hits.iter().map(|hit| hit.file).collect::<std::collections::HashSet<_>>().len();
+                let more = if self.truncated { "+" } else { "" };
+                let searching = if self.searching.is_some() { " (searching)" } else { "" };
+                TextLine::raw(format!("{}{more} matches in {files} files{searching} · {HELP}", self.hits.len()))
+            }
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+
+    // "file:line:text" with the same colors as the normal output.
+    fn hit_line(&self, hit: &Hit) -> TextLine<'static> {
+        let loaded = &self.files[hit.file];
+        let line = &loaded.lines[hit.line];
+        // A tab is one byte like a space, so the ranges still point to the same text after this.
+        let text = line.text.replace('\t', " ");
+
+        let mut spans = vec![
+            Span::styled(loaded.path.display().to_string(), Style::new().fg(Color::Magenta)),
+            Span::styled(":", Style::new().fg(Color::Cyan)),
+            Span::styled((line.index + 1).to_string(), Style::new().fg(Color::Green)),
+            Span::styled(":", Style::new().fg(Color::Cyan)),
+        ];
+        let mut printed = 0;
+        for columns in hit.found.iter().filter(|columns| !columns.is_empty()) {
+            spans.push(Span::raw(text[printed..columns.start].to_string()));
+            spans.push(Span::styled(
+                text[columns.clone()].to_string(),
+                Style::new().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ));
+            printed = columns.end;
+        }
+        spans.push(Span::raw(text[printed..].to_string()));
+
+        TextLine::from(spans)
+    }
+
+    // The editor gets the whole terminal while it's open. Afterwards the file is read again,
+    // it was probably changed, and the results are updated to match.
+    fn open_selected(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn Error>> {
+        let Some(hit) = self.list.selected().and_then(|selected| self.hits.get(selected)) else {
+            return Ok(());
+        };
+        let file = hit.file;
+        let path = self.files[file].path.clone();
+        let line = self.files[file].lines[hit.line].index + 1;
+        let selected = self.list.selected();
+
+        ratatui::restore();
+        let status = editor_command(&editor(), &path, line).status();
+        *terminal = ratatui::init();
+        terminal.clear()?;
+
+        if let Ok(Some(lines)) = read_file(&self.config, &path) {
+            self.files[file] = Arc::new(Loaded { path, lines });
+        }
+        self.search();
+        self.wait();
+        if selected.is_some_and(|selected| selected < self.hits.len()) {
+            self.list.select(selected);
+        }
+
+        // A missing editor is shown in the status line, there is no reason to close minigrep because of it.
+        match status {
+            Ok(status) if !status.success() => self.error = Some(format!("the editor exited with {status}")),
+            Ok(_) => {}
+            Err(e) => self.error = Some(format!("can't start the editor: {e}")),
+        }
+        Ok(())
+    }
+}
+
+// Goes through every line of every file, this runs on the search thread. None means it was cancelled.
+fn find(config: &Config, matcher: &Matcher, files: &[Arc<Loaded>], cancelled: &AtomicBool) -> Option<Found> {
+    let mut hits = Vec::new();
+
+    for (file, loaded) in files.iter().enumerate() {
+        let mut count = 0;
+        for (line, text) in loaded.lines.iter().enumerate() {
+            if cancelled.load(Ordering::Relaxed) {
+                return None;
+            }
+            let Some(found) = select(config, matcher, &text.text) else {
+                continue;
+            };
+            hits.push(Hit { file, line, found });
+
+            if hits.len() == MAX_HITS {
+                return Some(Found { hits, truncated: true });
+            }
+            // -m is still the most matches per file.
+            count += 1;
+            if config.max_count.is_some_and(|max| count >= max) {
+                break;
+            }
+        }
+    }
+
+    Some(Found { hits, truncated: false })
+}
+
+// $EDITOR, like the help line says. $VISUAL isn't looked at, vi is used when $EDITOR isn't set.
+fn editor() -> String {
+    env::var("EDITOR")
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| String::from("vi"))
+}
+
+// The editor variable can have arguments in it too, like "code --wait".
+// Most terminal editors (vi, vim, nano, emacs, micro...) open a file at a line with +LINE before the file,
+// VS Code and Sublime Text want "file:line" instead.
+fn editor_command(editor: &str, file: &Path, line: usize) -> Command {
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let mut command = Command::new(program);
+    command.args(words);
+
+    let name = Path::new(program).file_stem().and_then(|name| name.to_str()).unwrap_or_default();
+    match name {
+        "code" | "code-insiders" | "codium" => command.arg("--goto").arg(format!("{}:{line}", file.display())),
+        "subl" => command.arg(format!("{}:{line}", file.display())),
+        _ => command.arg(format!("+{line}")).arg(file),
+    };
+
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    fn app(args: &[&str], contents: &[(&str, &str)]) -> App {
+        let args: Vec<String> = ["minigrep", "--interactive"].iter().chain(args).map(|arg| arg.to_string()).collect();
+        let config = Config::build_layered(&args, Vec::new()).unwrap();
+        let files = contents
+            .iter()
+            .map(|(path, text)| {
+                Arc::new(Loaded {
+                    path: PathBuf::from(path),
+                    lines: input::read_lines(text.as_bytes()).map(Result::unwrap).collect(),
+                })
+            })
+            .collect();
+
+        let mut app = App::new(config, files);
+        app.wait();
+        app
+    }
+
+    // The search runs on its own thread, the tests look at the results once it's done.
+    fn press(app: &mut App, key: KeyEvent) {
+        app.key(key);
+        app.wait();
+    }
+
+    fn typed(app: &mut App, text: &str) {
+        for c in text.chars() {
+            press(app, KeyEvent::from(KeyCode::Char(c)));
+        }
+    }
+
+    fn hits(app: &App) -> Vec<(&str, usize)> {
+        app.hits
+            .iter()
+            .map(|hit| (app.files[hit.file].path.to_str().unwrap(), app.files[hit.file].lines[hit.line].index + 1))
+            .collect()
+    }
+
+    const FILES: &[(&str, &str)] = &[
+        ("poem.txt", "I'm nobody! Who are you?\nAre you nobody, too?"),
+        ("rust.txt", "Rust:\nsafe, fast, productive.\nTrust me."),
+    ];
+
+    #[test]
+    fn results_follow_the_query() {
+        let mut app = app(&[], FILES);
+        assert!(hits(&app).is_empty());
+
+        typed(&mut app, "ust");
+        assert_eq!(vec![("rust.txt", 1), ("rust.txt", 3)], hits(&app));
+        assert_eq!(Some(0), app.list.selected());
+
+        typed(&mut app, " ");
+        assert_eq!(vec![("rust.txt", 3)], hits(&app));
+        press(&mut app, KeyEvent::from(KeyCode::Backspace));
+        assert_eq!(2, hits(&app).len());
+
+        // Ctrl-U starts over.
+        press(&mut app, KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL));
+        typed(&mut app, "you");
+        assert_eq!(vec![("poem.txt", 1), ("poem.txt", 2)], hits(&app));
+    }
+
+    #[test]
+    fn tab_toggles_ignore_case() {
+        let mut app = app(&["-e", "rust"], FILES);
+        assert_eq!(vec![("rust.txt", 3)], hits(&app));
+
+        press(&mut app, KeyEvent::from(KeyCode::Tab));
+        assert!(app.config.ignore_case);
+        assert_eq!(vec![("rust.txt", 1), ("rust.txt", 3)], hits(&app));
+    }
+
+    #[test]
+    fn unfinished_regex_is_shown_as_an_error() {
+        let mut app = app(&["--regex"], FILES);
+
+        typed(&mut app, "(no");
+        assert!(app.error.is_some());
+        assert!(hits(&app).is_empty());
+
+        typed(&mut app, ")body");
+        assert_eq!(None, app.error);
+        assert_eq!(vec![("poem.txt", 1), ("poem.txt", 2)], hits(&app));
+    }
+
+    #[test]
+    fn scrolling() {
+        let mut app = app(&["-e", "o"], FILES);
+
+        press(&mut app, KeyEvent::from(KeyCode::Down));
+        assert_eq!(Some(1), app.list.selected());
+        press(&mut app, KeyEvent::from(KeyCode::Up));
+        press(&mut app, KeyEvent::from(KeyCode::Up));
+        assert_eq!(Some(0), app.list.selected());
+    }
+
+    #[test]
+    fn typing_cancels_the_running_search() {
+        let mut app = app(&[], FILES);
+
+        app.key(KeyEvent::from(KeyCode::Char('o')));
+        let first = Arc::clone(&app.searching.as_ref().unwrap().cancelled);
+        app.key(KeyEvent::from(KeyCode::Char('u')));
+        assert!(first.load(Ordering::Relaxed));
+
+        // Only the last query is shown.
+        app.wait();
+        assert_eq!(vec![("poem.txt", 1), ("poem.txt", 2)], hits(&app));
+    }
+
+    #[test]
+    fn editor_arguments() {
+        let args = |command: &Command| command.get_args().map(OsStr::to_os_string).collect::<Vec<_>>();
+
+        let vim = editor_command("vim", Path::new("src/lib.rs"), 12);
+        assert_eq!(OsStr::new("vim"), vim.get_program());
+        assert_eq!(vec!["+12", "src/lib.rs"], args(&vim));
+
+        let code = editor_command("/usr/bin/code --wait", Path::new("src/lib.rs"), 12);
+        assert_eq!(vec!["--wait", "--goto", "src/lib.rs:12"], args(&code));
+    }
+}
@@ -0,0 +1,335 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+use crate::index::INDEX_FILE;
+
+// The files with ignore rules, read in every directory of the tree. .ignore is read last,
+// so it can undo a rule of .gitignore without touching the file git uses.
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+// Include and exclude globs are matched against the path relative to the searched directory,
+// so "target/**" means the target directory at the top of the tree and "*.rs" matches at any depth.
+//
+// On top of the globs, hidden files and directories (the name starts with a dot) are skipped,
+// and so is everything the .gitignore and .ignore files of the tree ignore. --hidden and --no-ignore turn these off.
+pub struct Filter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    hidden: bool,
+    ignore_files: bool,
+}
+
+impl Filter {
+    pub fn build(include: &[String], exclude: &[String]) -> Result<Filter, globset::Error> {
+        // No include globs means every file is included.
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(glob_set(include)?)
+        };
+        let exclude = glob_set(exclude)?;
+
+        Ok(Filter { include, exclude, hidden: false, ignore_files: true })
+    }
+
+    // Search hidden files and directories too.
+    pub fn hidden(mut self, hidden: bool) -> Filter {
+        self.hidden = hidden;
+        self
+    }
+
+    // Follow the rules in .gitignore and .ignore files.
+    pub fn ignore_files(mut self, ignore_files: bool) -> Filter {
+        self.ignore_files = ignore_files;
+        self
+    }
+
+    fn includes(&self, relative: &Path) -> bool {
+        if self.exclude.is_match(relative) {
+            return false;
+        }
+
+        match &self.include {
+            Some(include) => include.is_match(relative),
+            None => true,
+        }
+    }
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+
+    builder.build()
+}
+
+// A path that couldn't be read, shown the way grep shows it: "path: error".
+#[derive(Debug)]
+pub struct PathError {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+// What a walk found. A directory that can't be read doesn't stop the walk, it ends up in errors and the rest is still walked.
+pub struct Walk {
+    pub files: Vec<PathBuf>,
+    pub errors: Vec<PathError>,
+}
+
+// Collects every file under root that passes the filter. The files are sorted,
+// so searching the same tree twice prints the results in the same order.
+pub fn files(root: &Path, filter: &Filter) -> Walk {
+    let mut walk = Walk { files: Vec::new(), errors: Vec::new() };
+    visit(root, root, filter, &mut Vec::new(), &mut walk);
+    walk.files.sort();
+
+    walk
+}
+
+// ignores has the rules of every directory from the root down to dir, the closest directory last.
+fn visit(root: &Path, dir: &Path, filter: &Filter, ignores: &mut Vec<Gitignore>, walk: &mut Walk) {
+    let rules = if filter.ignore_files { ignore_rules(dir) } else { None };
+    let has_rules = rules.is_some();
+    ignores.extend(rules);
+
+    if let Err(error) = visit_entries(root, dir, filter, ignores, walk) {
+        walk.errors.push(PathError { path: dir.to_path_buf(), error });
+    }
+
+    if has_rules {
+        ignores.pop();
+    }
+}
+
+fn visit_entries(root: &Path, dir: &Path, filter: &Filter, ignores: &mut Vec<Gitignore>, walk: &mut Walk) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        // file_type doesn't follow symlinks, so a link to a directory is never entered.
+        // Otherwise a link like "up -> .." would walk the same files over and over until the path gets too long.
+        let file_type = entry.file_type()?;
+        let is_dir = file_type.is_dir();
+
+        // A link to a file is searched like the file. A link to a directory is skipped, and a broken link
+        // is kept so the search reports it.
+        if file_type.is_symlink() && fs::metadata(&path).is_ok_and(|metadata| metadata.is_dir()) {
+            continue;
+        }
+
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !filter.hidden && name.starts_with('.') {
+            continue;
+        }
+        // The index (and the temporary file it's written to first) is never searched, not even with --hidden.
+        // Its paths and trigrams would match all kinds of queries.
+        if name.starts_with(INDEX_FILE) {
+            continue;
+        }
+        if is_ignored(ignores, &path, is_dir) {
+            continue;
+        }
+
+        if is_dir {
+            // Excluded directories are skipped as a whole, there is no need to look inside them.
+            if !filter.exclude.is_match(relative) {
+                visit(root, &path, filter, ignores, walk);
+            }
+        } else if filter.includes(relative) {
+            walk.files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+// The rules of the ignore files in dir, None if it doesn't have any.
+fn ignore_rules(dir: &Path) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+
+    for name in IGNORE_FILES {
+        let file = dir.join(name);
+        if file.is_file() {
+            // A broken line only loses that one rule, the rest of the file still works like it does in git.
+            let _ = builder.add(file);
+        }
+    }
+
+    builder.build().ok().filter(|rules| !rules.is_empty())
+}
+
+// The closest directory with a rule for the path decides, so a nested .gitignore can
+// ignore more, or bring back a file with a "!" rule that a parent directory ignored.
+fn is_ignored(ignores: &[Gitignore], path: &Path, is_dir: bool) -> bool {
+    for rules in ignores.iter().rev() {
+        match rules.matched(path, is_dir) {
+            Match::None => continue,
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    // Builds a small tree in the temp directory, every test gets its own directory.
+    fn tree(name: &str) -> TempDir {
+        let root = TempDir::new(&format!("walk_{name}"));
+
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::write(root.join("README.md"), "readme").unwrap();
+        fs::write(root.join("src/lib.rs"), "lib").unwrap();
+        fs::write(root.join("src/nested/mod.rs"), "nested").unwrap();
+        fs::write(root.join("target/debug/out.rs"), "generated").unwrap();
+
+        root
+    }
+
+    fn relative(root: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .iter()
+            .map(|file| file.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect()
+    }
+
+    #[test]
+    fn walks_every_file() {
+        let root = tree("all");
+        let filter = Filter::build(&[], &[]).unwrap();
+
+        assert_eq!(
+            vec!["README.md", "src/lib.rs", "src/nested/mod.rs", "target/debug/out.rs"],
+            relative(&root, files(&root, &filter).files)
+        );
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let root = tree("globs");
+        let filter = Filter::build(&["*.rs".to_string()], &["target/**".to_string()]).unwrap();
+
+        assert_eq!(
+            vec!["src/lib.rs", "src/nested/mod.rs"],
+            relative(&root, files(&root, &filter).files)
+        );
+    }
+
+    // A repository with ignore files, a nested one brings back a file the top one ignores.
+    fn repository(name: &str) -> TempDir {
+        let root = tree(name);
+
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("node_modules/dep")).unwrap();
+        fs::write(root.join(".git/config"), "git").unwrap();
+        fs::write(root.join(".env"), "secret").unwrap();
+        fs::write(root.join("node_modules/dep/index.js"), "dep").unwrap();
+        fs::write(root.join("src/debug.log"), "log").unwrap();
+        fs::write(root.join("src/nested/keep.log"), "log").unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\nnode_modules\n").unwrap();
+        fs::write(root.join("src/nested/.gitignore"), "!keep.log\n").unwrap();
+        // .ignore wins over .gitignore in the same directory.
+        fs::write(root.join(".ignore"), "!README.md\nREADME.md\n").unwrap();
+
+        root
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        let root = tree("symlinks");
+        std::os::unix::fs::symlink("..", root.join("src/up")).unwrap();
+        std::os::unix::fs::symlink("lib.rs", root.join("src/link.rs")).unwrap();
+        std::os::unix::fs::symlink(root.join("missing"), root.join("dangling.txt")).unwrap();
+        let filter = Filter::build(&[], &[]).unwrap();
+
+        let walk = files(&root, &filter);
+        assert_eq!(
+            vec!["README.md", "dangling.txt", "src/lib.rs", "src/link.rs", "src/nested/mod.rs", "target/debug/out.rs"],
+            relative(&root, walk.files)
+        );
+        assert!(walk.errors.is_empty());
+    }
+
+    #[test]
+    fn unreadable_directory_is_an_error() {
+        let root = tree("missing");
+        let filter = Filter::build(&[], &[]).unwrap();
+
+        let walk = files(&root.join("nothing here"), &filter);
+        assert!(walk.files.is_empty());
+        assert_eq!(1, walk.errors.len());
+        assert_eq!(root.join("nothing here"), walk.errors[0].path);
+    }
+
+    #[test]
+    fn index_is_skipped_even_with_hidden() {
+        let root = tree("index");
+        fs::write(root.join(INDEX_FILE), "index").unwrap();
+        fs::write(root.join(format!("{INDEX_FILE}.123.tmp")), "index").unwrap();
+        fs::write(root.join(".env"), "secret").unwrap();
+        let filter = Filter::build(&[], &[]).unwrap().hidden(true);
+
+        assert_eq!(
+            vec![".env", "README.md", "src/lib.rs", "src/nested/mod.rs", "target/debug/out.rs"],
+            relative(&root, files(&root, &filter).files)
+        );
+    }
+
+    #[test]
+    fn ignore_files_and_hidden_files_are_skipped() {
+        let root = repository("ignored");
+        let filter = Filter::build(&[], &[]).unwrap();
+
+        assert_eq!(
+            vec!["src/lib.rs", "src/nested/keep.log", "src/nested/mod.rs"],
+            relative(&root, files(&root, &filter).files)
+        );
+    }
+
+    #[test]
+    fn no_ignore_and_hidden() {
+        let root = repository("not_ignored");
+
+        let filter = Filter::build(&[], &[]).unwrap().ignore_files(false);
+        assert_eq!(
+            vec![
+                "README.md",
+                "node_modules/dep/index.js",
+                "src/debug.log",
+                "src/lib.rs",
+                "src/nested/keep.log",
+                "src/nested/mod.rs",
+                "target/debug/out.rs",
+            ],
+            relative(&root, files(&root, &filter).files)
+        );
+
+        let filter = Filter::build(&[], &["**/.git".to_string()]).unwrap().hidden(true);
+        assert_eq!(
+            vec![".env", ".gitignore", ".ignore", "src/lib.rs", "src/nested/.gitignore", "src/nested/keep.log", "src/nested/mod.rs"],
+            relative(&root, files(&root, &filter).files)
+        );
+    }
+}
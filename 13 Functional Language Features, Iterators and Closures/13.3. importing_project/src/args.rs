@@ -0,0 +1,186 @@
+use std::error::Error;
+use std::fmt;
+
+// Every flag minigrep understands. The parser and the --help screen are both generated from this table,
+// so adding a flag here is enough to make it parse and show up in the usage.
+// This version only has switches, none of the flags take a value.
+pub struct Flag {
+    pub short: char,
+    pub long: &'static str,
+    pub help: &'static str,
+}
+
+pub const FLAGS: &[Flag] = &[
+    Flag { short: 'i', long: "ignore-case", help: "Ignore case distinctions in the query" },
+    Flag { short: 'n', long: "line-number", help: "Prefix each matching line with its line number" },
+    Flag { short: 'c', long: "count", help: "Print only the number of matching lines" },
+    Flag { short: 'h', long: "help", help: "Print this help and exit" },
+];
+
+// Errors are typed, so the caller can tell a help request apart from an actual mistake.
+#[derive(Debug, PartialEq)]
+pub enum ArgsError {
+    HelpRequested,
+    UnknownFlag(String),
+    UnexpectedValue(String),
+    MissingQuery,
+    MissingPath,
+    UnexpectedArgument(String),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::HelpRequested => write!(f, "help requested"),
+            ArgsError::UnknownFlag(flag) => write!(f, "unknown flag '{flag}'"),
+            ArgsError::UnexpectedValue(flag) => write!(f, "flag '{flag}' doesn't take a value"),
+            ArgsError::MissingQuery => write!(f, "didn't get a query string"),
+            ArgsError::MissingPath => write!(f, "didn't get a file path"),
+            ArgsError::UnexpectedArgument(arg) => write!(f, "unexpected argument '{arg}'"),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+#[derive(Debug, PartialEq)]
+pub struct Parsed {
+    // The flags that were found on the command line, named by their long name so "-i" and "--ignore-case" look the same.
+    pub opts: Vec<&'static str>,
+    pub positional: Vec<String>,
+}
+
+// Parses the arguments without the program name. Flags keep their order, so a later flag can overwrite an earlier one.
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Parsed, ArgsError> {
+    let mut opts = Vec::new();
+    let mut positional = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            // Everything after "--" is positional, which is how a query starting with "-" can be searched.
+            positional.extend(args.by_ref());
+        } else if let Some(long) = arg.strip_prefix("--") {
+            // "--count=yes" is a mistake, a switch is turned on just by being there.
+            if let Some((name, _)) = long.split_once('=') {
+                return Err(ArgsError::UnexpectedValue(format!("--{name}")));
+            }
+            let flag = FLAGS
+                .iter()
+                .find(|flag| flag.long == long)
+                .ok_or_else(|| ArgsError::UnknownFlag(format!("--{long}")))?;
+
+            opts.push(flag.long);
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            // Short flags can be combined ("-in").
+            for short in arg[1..].chars() {
+                let flag = FLAGS
+                    .iter()
+                    .find(|flag| flag.short == short)
+                    .ok_or_else(|| ArgsError::UnknownFlag(format!("-{short}")))?;
+
+                opts.push(flag.long);
+            }
+        } else {
+            // A lone "-" is positional too, it's the usual name for stdin.
+            positional.push(arg);
+        }
+    }
+
+    if opts.contains(&"help") {
+        return Err(ArgsError::HelpRequested);
+    }
+
+    Ok(Parsed { opts, positional })
+}
+
+// The usage screen is generated from FLAGS, so it can't get out of date.
+pub fn usage() -> String {
+    let mut usage = String::from("Usage: minigrep [OPTIONS] [--] <QUERY> <PATH>\n\nOptions:\n");
+
+    for flag in FLAGS {
+        let long = format!("--{}", flag.long);
+        usage.push_str(&format!("  -{}, {long:<24} {}\n", flag.short, flag.help));
+    }
+
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn positional_only() {
+        let parsed = parse(args(&["duct", "poem.txt"])).unwrap();
+
+        assert!(parsed.opts.is_empty());
+        assert_eq!(vec!["duct", "poem.txt"], parsed.positional);
+    }
+
+    #[test]
+    fn short_and_long_flags() {
+        let short = parse(args(&["-i", "duct", "poem.txt"])).unwrap();
+        let long = parse(args(&["duct", "--ignore-case", "poem.txt"])).unwrap();
+
+        assert_eq!(vec!["ignore-case"], short.opts);
+        assert_eq!(short, long);
+    }
+
+    #[test]
+    fn combined_short_flags() {
+        let parsed = parse(args(&["-inc", "duct", "poem.txt"])).unwrap();
+
+        assert_eq!(
+            vec!["ignore-case", "line-number", "count"],
+            parsed.opts
+        );
+    }
+
+    #[test]
+    fn double_dash_ends_flags() {
+        let parsed = parse(args(&["-n", "--", "-i", "poem.txt"])).unwrap();
+
+        assert_eq!(vec!["line-number"], parsed.opts);
+        assert_eq!(vec!["-i", "poem.txt"], parsed.positional);
+    }
+
+    #[test]
+    fn single_dash_is_positional() {
+        let parsed = parse(args(&["duct", "-"])).unwrap();
+
+        assert_eq!(vec!["duct", "-"], parsed.positional);
+    }
+
+    #[test]
+    fn unknown_flags() {
+        assert_eq!(Err(ArgsError::UnknownFlag("--nope".to_string())), parse(args(&["--nope"])));
+        assert_eq!(Err(ArgsError::UnknownFlag("-z".to_string())), parse(args(&["-iz"])));
+    }
+
+    #[test]
+    fn unexpected_values() {
+        assert_eq!(
+            Err(ArgsError::UnexpectedValue("--count".to_string())),
+            parse(args(&["--count=yes"]))
+        );
+    }
+
+    #[test]
+    fn help_wins_over_everything_else() {
+        assert_eq!(Err(ArgsError::HelpRequested), parse(args(&["-h"])));
+        assert_eq!(Err(ArgsError::HelpRequested), parse(args(&["-ni", "--help", "duct"])));
+    }
+
+    #[test]
+    fn usage_lists_every_flag() {
+        let usage = usage();
+
+        for flag in FLAGS {
+            assert!(usage.contains(&format!("--{}", flag.long)));
+        }
+    }
+}
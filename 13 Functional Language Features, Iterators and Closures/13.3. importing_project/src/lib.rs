@@ -2,47 +2,69 @@ use std::error::Error;
 use std::fs;
 use std::env;
 
+pub mod args;
+
+use args::ArgsError;
+
 pub struct Config {
     pub query: String,
     pub file_path: String,
     pub ignore_case: bool,
+    pub line_number: bool,
+    pub count: bool,
 }
 
 impl Config {
     // The parameter type has been modified, now it's mutable, that implements Iterator with a String value
-    pub fn build(mut args: impl Iterator<Item = String>)-> Result<Config, &'static str> {
+    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, ArgsError> {
         // Iterate once so the variables get correct values
         args.next();
 
+        // The parser goes through the rest of the iterator, flags can be anywhere between the query and the path.
+        let parsed = args::parse(args)?;
+        let mut positional = parsed.positional.into_iter();
+
         // Iterators can populate variables, without then need of cloning method (which somewhat slower)
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string")
-        };
+        let query = positional.next().ok_or(ArgsError::MissingQuery)?;
+        let file_path = positional.next().ok_or(ArgsError::MissingPath)?;
 
-        let file_path = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file path")
-        };
+        if let Some(extra) = positional.next() {
+            return Err(ArgsError::UnexpectedArgument(extra));
+        }
 
-        // Earlier the ownership wasn't guaranteed which is why this part included if-statement
-        let ignore_case = env::var("IGNORE_CASE").is_ok();
+        // The environment variable still works, -i is just a shorter way of saying the same thing.
+        let ignore_case = env::var("IGNORE_CASE").is_ok() || parsed.opts.contains(&"ignore-case");
+        let line_number = parsed.opts.contains(&"line-number");
+        let count = parsed.opts.contains(&"count");
 
-        Ok(Config { query, file_path, ignore_case })
+        Ok(Config { query, file_path, ignore_case, line_number, count })
     }
 }
 
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
     let contents = fs::read_to_string(config.file_path)?;
 
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
-    } else {
-        search(&config.query, &contents)
+    let search_lines = |contents| {
+        if config.ignore_case {
+            search_case_insensitive(&config.query, contents)
+        } else {
+            search(&config.query, contents)
+        }
     };
 
-    for line in results {
-        println!("{line}");
+    if config.count {
+        println!("{}", search_lines(&contents).len());
+    } else if config.line_number {
+        // Searching one line at a time is how the line number is kept around.
+        contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !search_lines(line).is_empty())
+            .for_each(|(index, line)| println!("{}:{line}", index + 1));
+    } else {
+        for line in search_lines(&contents) {
+            println!("{line}");
+        }
     }
 
     Ok(())
@@ -81,10 +103,10 @@ mod tests {
     fn case_insensitive() {
         let query = "rUsT";
         let contents = "\
-            Rust:
-            safe, fast, productive.
-            Pick three.
-            Trust me.";
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
 
         assert_eq!(
             vec!["Rust:", "Trust me."],
@@ -96,10 +118,56 @@ mod tests {
     fn case_sensitive() {
         let query = "duct";
         let contents = "\
-            Rust:
-            safe, fast, productive.
-            Pick three.";
+Rust:
+safe, fast, productive.
+Pick three.";
 
         assert_eq!(vec!["safe, fast, productive."], search(query, contents));
     }
-}
\ No newline at end of file
+
+    fn build(args: &[&str]) -> Result<Config, ArgsError> {
+        Config::build(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn build_reads_query_and_path() {
+        let config = build(&["minigrep", "duct", "poem.txt"]).unwrap();
+
+        assert_eq!("duct", config.query);
+        assert_eq!("poem.txt", config.file_path);
+        assert!(!config.line_number && !config.count);
+    }
+
+    #[test]
+    fn build_reads_flags_in_any_position() {
+        let config = build(&["minigrep", "-n", "duct", "--count", "poem.txt", "-i"]).unwrap();
+
+        assert!(config.ignore_case && config.line_number && config.count);
+    }
+
+    #[test]
+    fn build_reads_combined_flags() {
+        let config = build(&["minigrep", "-in", "duct", "poem.txt"]).unwrap();
+
+        assert!(config.ignore_case && config.line_number && !config.count);
+    }
+
+    #[test]
+    fn build_query_after_double_dash() {
+        let config = build(&["minigrep", "-c", "--", "-n", "poem.txt"]).unwrap();
+
+        assert_eq!("-n", config.query);
+        assert!(config.count && !config.line_number);
+    }
+
+    #[test]
+    fn build_errors() {
+        assert_eq!(Some(ArgsError::MissingQuery), build(&["minigrep"]).err());
+        assert_eq!(Some(ArgsError::MissingPath), build(&["minigrep", "duct"]).err());
+        assert_eq!(
+            Some(ArgsError::UnexpectedArgument("extra".to_string())),
+            build(&["minigrep", "duct", "poem.txt", "extra"]).err()
+        );
+        assert_eq!(Some(ArgsError::HelpRequested), build(&["minigrep", "-h"]).err());
+    }
+}
@@ -1,13 +1,20 @@
 use std::env;
 use std::process;
 
+use minigrep::args::{self, ArgsError};
 use minigrep::Config;
 
 fn main() {
     // let args: Vec<String> = env::args().collect();
 
     let config = Config::build(env::args()).unwrap_or_else(|err| {
+        if err == ArgsError::HelpRequested {
+            print!("{}", args::usage());
+            process::exit(0);
+        }
+
         eprintln!("Problem parsing arguments: {err}");
+        eprintln!("Try 'minigrep --help' for more information.");
         process::exit(1);
     });
 
@@ -1,13 +1,292 @@
 use std::{
-    sync::{mpsc, Arc, Mutex},
-    thread
+    any::Any,
+    error::Error,
+    fmt,
+    io,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError},
+    thread,
+    time::{Duration, Instant}
 };
 
+mod queue;
+
+use queue::{Pop, Queue};
+pub use queue::{QueueFull, QueuePolicy};
+
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+type PanicHandler = dyn Fn(Option<usize>, &(dyn Any + Send)) + Send + Sync;
+
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>
+    shared: Arc<Shared>
+}
+
+// What the pool and its workers share. The workers are kept in here too, so a dying worker can put its replacement on the list.
+struct Shared {
+    queue: Queue,
+    settings: Settings,
+    sizing: Mutex<Sizing>,
+    workers: Mutex<Vec<Worker>>
+}
+
+// How many workers there should be and are. The sizing is always locked before the workers when both are needed.
+struct Sizing {
+    min: usize,
+    max: usize,
+    // The workers that are running and haven't decided to retire.
+    live: usize,
+    next_id: usize
+}
+
+impl Shared {
+    // Runs a job given to execute. Without catch_unwind a panicking job would take the whole thread down with it,
+    // a worker or the caller's. worker is None when the caller runs the job itself.
+    fn run(&self, worker: Option<usize>, job: Job) {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+            match (&self.settings.panic_handler, worker) {
+                (Some(handler), _) => handler(worker, &*payload),
+                (None, Some(id)) => println!("Worker {id} job panicked; carrying on."),
+                (None, None) => println!("Job panicked on the calling thread; carrying on."),
+            }
+        }
+    }
+
+    // More workers are running than the max allows, like after resize made the pool smaller.
+    // The queue is locked when this is called, so the sizing is locked after the queue here. Nothing locks them the other way around.
+    fn extra(&self) -> bool {
+        let sizing = lock(&self.sizing);
+        sizing.live > sizing.max
+    }
+
+    // Decides if a worker should stop. One above max stops right away, one above min only after it has been idle long enough.
+    fn retire(&self, idle_for: Duration) -> bool {
+        let mut sizing = lock(&self.sizing);
+        let retire = sizing.live > sizing.max || (sizing.live > sizing.min && idle_for >= self.settings.idle_timeout);
+        if retire {
+            sizing.live -= 1;
+        }
+        retire
+    }
+}
+
+// The part of the builder that the workers still need after the pool is built.
+struct Settings {
+    name_prefix: String,
+    stack_size: Option<usize>,
+    panic_handler: Option<Box<PanicHandler>>,
+    idle_timeout: Duration
+}
+
+// A panic while a lock is held poisons the Mutex, and lock().unwrap() would then panic in every other worker too.
+// Nothing behind these locks is left half way done by a panic, so the poison can be ignored.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The reasons `ThreadPool::build` and `ThreadPool::resize` can fail.
+#[derive(Debug)]
+pub enum PoolCreationError {
+    /// A pool without threads would never run anything.
+    ZeroSize,
+    /// A queue without room would never take a job.
+    ZeroCapacity,
+    /// The maximum size can't be smaller than the minimum.
+    MaxBelowMin { min: usize, max: usize },
+    /// The operating system refused to start the thread of worker `id`.
+    Spawn { id: usize, source: io::Error },
+}
+
+impl fmt::Display for PoolCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolCreationError::ZeroSize => write!(f, "a thread pool needs at least one thread"),
+            PoolCreationError::ZeroCapacity => write!(f, "the job queue needs room for at least one job"),
+            PoolCreationError::MaxBelowMin { min, max } => write!(f, "the maximum size {max} is smaller than the minimum size {min}"),
+            PoolCreationError::Spawn { id, source } => write!(f, "couldn't start the thread of worker {id}: {source}"),
+        }
+    }
+}
+
+impl Error for PoolCreationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolCreationError::ZeroSize | PoolCreationError::ZeroCapacity | PoolCreationError::MaxBelowMin { .. } => None,
+            PoolCreationError::Spawn { source, .. } => Some(source),
+        }
+    }
+}
+
+/// A way to wait for a job given to `ThreadPool::submit` and get what it returned.
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<thread::Result<T>>
+}
+
+/// Why a `JobHandle` didn't give a value.
+#[derive(Debug)]
+pub enum JobError {
+    /// The job panicked. This is the payload of the panic, same as `thread::JoinHandle::join` gives.
+    Panicked(Box<dyn Any + Send + 'static>),
+    /// The job didn't finish in time. It may still finish later, and the handle can be waited on again.
+    Timeout,
+    /// The job was dropped without running, or its result was already taken.
+    Cancelled,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // panic!("...") gives a &str payload and panic!("{x}") a String, anything else can't be shown.
+            JobError::Panicked(payload) => match payload.downcast_ref::<&str>() {
+                Some(message) => write!(f, "the job panicked: {message}"),
+                None => match payload.downcast_ref::<String>() {
+                    Some(message) => write!(f, "the job panicked: {message}"),
+                    None => write!(f, "the job panicked"),
+                },
+            },
+            JobError::Timeout => write!(f, "the job didn't finish in time"),
+            JobError::Cancelled => write!(f, "the job was cancelled"),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+impl<T> JobHandle<T> {
+    /// Blocks until the job is done and returns its value.
+    pub fn join(self) -> Result<T, JobError> {
+        match self.receiver.recv() {
+            Ok(result) => result.map_err(JobError::Panicked),
+            Err(_) => Err(JobError::Cancelled),
+        }
+    }
+
+    /// Like `join`, but gives up with `JobError::Timeout` after waiting for `timeout`.
+    pub fn join_timeout(&self, timeout: Duration) -> Result<T, JobError> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => result.map_err(JobError::Panicked),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(JobError::Timeout),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(JobError::Cancelled),
+        }
+    }
+}
+
+/// Settings of a `ThreadPool` that aren't needed most of the time.
+///
+/// ```
+/// use std::time::Duration;
+/// use hello::{QueuePolicy, ThreadPool};
+///
+/// let pool = ThreadPool::builder(4)
+///     .max_size(16)
+///     .idle_timeout(Duration::from_secs(30))
+///     .name_prefix("http")
+///     .stack_size(256 * 1024)
+///     .queue_capacity(100)
+///     .queue_policy(QueuePolicy::Reject)
+///     .build()
+///     .unwrap();
+/// ```
+pub struct ThreadPoolBuilder {
+    size: usize,
+    max_size: Option<usize>,
+    settings: Settings,
+    queue_capacity: Option<usize>,
+    queue_policy: QueuePolicy,
+}
+
+impl ThreadPoolBuilder {
+    /// How many workers the pool may have when there's more work than workers. By default it's the size given to `builder`.
+    ///
+    /// The size given to `builder` is then the minimum. A worker is started whenever a job comes in and none of the workers are free,
+    /// and the ones above the minimum stop after they've been idle for the `idle_timeout`.
+    pub fn max_size(mut self, size: usize) -> ThreadPoolBuilder {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// How long a worker above the minimum size waits for a job before it stops. The default is a minute.
+    pub fn idle_timeout(mut self, timeout: Duration) -> ThreadPoolBuilder {
+        self.settings.idle_timeout = timeout;
+        self
+    }
+
+    /// The threads are named "{prefix}-{id}", "worker-0" and so on by default.
+    /// The name shows up in panic messages and debuggers, which makes it easier to tell the threads apart.
+    pub fn name_prefix(mut self, prefix: &str) -> ThreadPoolBuilder {
+        self.settings.name_prefix = prefix.to_string();
+        self
+    }
+
+    /// The stack size of every thread in bytes. The default is what `std::thread` uses (2 MiB at the moment).
+    pub fn stack_size(mut self, bytes: usize) -> ThreadPoolBuilder {
+        self.settings.stack_size = Some(bytes);
+        self
+    }
+
+    /// Called with the id of the worker and the panic payload when a job given to `execute` panics.
+    /// The worker carries on with the next job afterwards. Without a handler the worker just prints a line about it.
+    /// The id is `None` when the job ran on the thread that called `execute`, because of `QueuePolicy::CallerRuns`.
+    ///
+    /// Panics of jobs given to `submit` go to their `JobHandle` instead.
+    /// If the handler itself panics the worker thread dies, and a new one is started in its place.
+    pub fn panic_handler<H>(mut self, handler: H) -> ThreadPoolBuilder
+        where H: Fn(Option<usize>, &(dyn Any + Send)) + Send + Sync + 'static,
+        {
+            self.settings.panic_handler = Some(Box::new(handler));
+            self
+        }
+
+    /// How many jobs can wait for a free worker. By default there's no limit, and a flood of jobs can take all the memory there is.
+    pub fn queue_capacity(mut self, jobs: usize) -> ThreadPoolBuilder {
+        self.queue_capacity = Some(jobs);
+        self
+    }
+
+    /// What to do with a new job when the queue is full. The default is `QueuePolicy::Block`.
+    /// Doesn't do anything without a `queue_capacity`.
+    pub fn queue_policy(mut self, policy: QueuePolicy) -> ThreadPoolBuilder {
+        self.queue_policy = policy;
+        self
+    }
+
+    /// Starts the threads.
+    ///
+    /// # Errors
+    ///
+    /// `PoolCreationError::ZeroSize` if the size is zero, `PoolCreationError::MaxBelowMin` if the maximum size is smaller than it,
+    /// `PoolCreationError::ZeroCapacity` if the queue capacity is zero and `PoolCreationError::Spawn` if a thread couldn't be started.
+    /// The threads that were already started are shut down before the error is returned.
+    pub fn build(self) -> Result<ThreadPool, PoolCreationError> {
+        if self.size == 0 {
+            return Err(PoolCreationError::ZeroSize);
+        }
+        let max = self.max_size.unwrap_or(self.size);
+        if max < self.size {
+            return Err(PoolCreationError::MaxBelowMin { min: self.size, max });
+        }
+        if self.queue_capacity == Some(0) {
+            return Err(PoolCreationError::ZeroCapacity);
+        }
+
+        let shared = Arc::new(Shared {
+            queue: Queue::new(self.queue_capacity, self.queue_policy),
+            settings: self.settings,
+            sizing: Mutex::new(Sizing { min: self.size, max, live: 0, next_id: 0 }),
+            workers: Mutex::new(Vec::with_capacity(self.size))
+        });
+        // The pool is put together first, so returning early with ? drops it and the Drop shuts down the workers that did start.
+        let pool = ThreadPool { shared };
+
+        let mut sizing = lock(&pool.shared.sizing);
+        for _ in 0..self.size {
+            pool.spawn_worker(&mut sizing)?;
+        }
+        drop(sizing);
+
+        Ok(pool)
+    }
 }
 
 impl ThreadPool {
@@ -17,43 +296,177 @@ impl ThreadPool {
     ///
     /// # Panics
     ///
-    /// The `new` function will panic if the size is zero.
+    /// The `new` function will panic if the size is zero or if a thread can't be started.
+    /// Use `build` to handle those instead.
     pub fn new(size: usize) -> ThreadPool {
-        assert!(size > 0);
+        match ThreadPool::build(size) {
+            Ok(pool) => pool,
+            Err(e) => panic!("{e}"),
+        }
+    }
 
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
+    /// Create a new ThreadPool, or tell why it couldn't be created.
+    ///
+    /// The size is the number of threads in the pool. `builder` has the rest of the settings.
+    pub fn build(size: usize) -> Result<ThreadPool, PoolCreationError> {
+        ThreadPool::builder(size).build()
+    }
 
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+    /// Starts configuring a ThreadPool with `size` threads, or at least `size` threads with `max_size`.
+    pub fn builder(size: usize) -> ThreadPoolBuilder {
+        ThreadPoolBuilder {
+            size,
+            max_size: None,
+            settings: Settings {
+                name_prefix: String::from("worker"),
+                stack_size: None,
+                panic_handler: None,
+                idle_timeout: Duration::from_secs(60)
+            },
+            queue_capacity: None,
+            queue_policy: QueuePolicy::Block
         }
-
-        ThreadPool { workers, sender: Some(sender) }
     }
+
+    /// Queues the closure for the next free worker.
+    ///
+    /// With `QueuePolicy::Reject` a job that doesn't fit in the queue is dropped, and a line about it is printed to stderr.
+    /// `try_execute` gives the caller the error instead.
     // Don't mind the styling. This is cleaner and easier to read for me.
     pub fn execute<F>(&self, f: F)
         where F: FnOnce() + Send + 'static, 
+        {
+            // Only QueuePolicy::Reject can fail. A job that silently never runs is a nasty thing to debug,
+            // so it's at least said out loud, like the workers do with panics.
+            if let Err(e) = self.try_execute(f) {
+                eprintln!("Job dropped: {e}.");
+            }
+        }
+
+    /// Like `execute`, but tells when the queue rejected the job.
+    ///
+    /// # Errors
+    ///
+    /// `QueueFull` if the queue is full and the policy is `QueuePolicy::Reject`. The other policies always take the job.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), QueueFull>
+        where F: FnOnce() + Send + 'static,
         {
             let job = Box::new(f);
+
+            // Nobody is free to take the job, so there's one more worker if the max size allows it.
+            // This is done before the job is queued, so a caller that is blocked by a full queue doesn't wait for workers that never come.
+            if self.shared.queue.needs_worker() {
+                let mut sizing = lock(&self.shared.sizing);
+                if sizing.live < sizing.max {
+                    // The job still gets done by the workers there are, so failing to start one more isn't the caller's problem.
+                    if let Err(e) = self.spawn_worker(&mut sizing) {
+                        eprintln!("{e}");
+                    }
+                }
+            }
             
-            // Compared to previous 'send' execution we need to process the Some to access the sender and use send method.
-            // self.sender.send(job).unwrap();
-            self.sender.as_ref().unwrap().send(job).unwrap();
+            // The sender of the channel used to be here, now the queue decides what to do when it's full.
+            // self.sender.as_ref().unwrap().send(job).unwrap();
+            // With QueuePolicy::CallerRuns a job that doesn't fit comes back to be run right here.
+            if let Some(job) = self.shared.queue.push(job)? {
+                self.shared.run(None, job);
+            }
+            Ok(())
+        }
+
+    /// Runs the closure on the pool like `execute`, and returns a handle to get its return value with.
+    ///
+    /// A panic in the closure doesn't take the worker down. The payload is given to whoever joins the handle instead.
+    /// If the queue rejects the job or drops it for a newer one, joining the handle gives `JobError::Cancelled`.
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static,
+        {
+            // Every job gets its own channel that carries only the one result.
+            let (sender, receiver) = mpsc::channel();
+
+            // A rejected job is dropped along with its sender, and that is what tells the handle it was cancelled.
+            self.execute(move || {
+                // The closure isn't used after a panic, so it doesn't matter if it's left half way through something.
+                let result = panic::catch_unwind(AssertUnwindSafe(f));
+                // Nobody is listening if the handle was dropped, that's fine.
+                let _ = sender.send(result);
+            });
+
+            JobHandle { receiver }
+        }
+}
+
+impl ThreadPool {
+    /// How many workers the pool has right now.
+    pub fn size(&self) -> usize {
+        lock(&self.shared.sizing).live
+    }
+
+    /// Changes the minimum size of the pool to `size` while it's running. The maximum size moves by as much,
+    /// so a pool without `max_size` stays at exactly `size` workers.
+    ///
+    /// The missing workers are started right away. The extra ones stop after they finish the job they have, if they have one.
+    ///
+    /// # Errors
+    ///
+    /// `PoolCreationError::ZeroSize` if the size is zero and `PoolCreationError::Spawn` if a thread couldn't be started.
+    /// The pool keeps the new size and the workers that did start either way.
+    pub fn resize(&self, size: usize) -> Result<(), PoolCreationError> {
+        if size == 0 {
+            return Err(PoolCreationError::ZeroSize);
+        }
+
+        let mut sizing = lock(&self.shared.sizing);
+        let headroom = sizing.max - sizing.min;
+        sizing.min = size;
+        sizing.max = size + headroom;
+
+        let mut result = Ok(());
+        while sizing.live < sizing.min && result.is_ok() {
+            result = self.spawn_worker(&mut sizing);
         }
+        drop(sizing);
+
+        // The idle workers check if they're extra now.
+        self.shared.queue.wake_all();
+        result
+    }
+
+    fn spawn_worker(&self, sizing: &mut Sizing) -> Result<(), PoolCreationError> {
+        let id = sizing.next_id;
+        let worker = Worker::new(id, Arc::clone(&self.shared))
+            .map_err(|source| PoolCreationError::Spawn { id, source })?;
+        sizing.next_id += 1;
+        sizing.live += 1;
+
+        let mut workers = lock(&self.shared.workers);
+        // The workers that have retired are still on the list, they're cleaned up here so the list doesn't grow forever.
+        workers.retain(|worker| worker.thread.as_ref().is_some_and(|thread| !thread.is_finished()));
+        workers.push(worker);
+        Ok(())
+    }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
+        self.shared.queue.close();
 
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+        // A worker that dies during the shutdown still puts its replacement on the list, so this goes on until the list stays empty.
+        loop {
+            let workers = mem::take(&mut *lock(&self.shared.workers));
+            if workers.is_empty() {
+                break;
+            }
 
-            // We call take on the Option value to move thread out of worker.
-            if let Some(thread) = worker.thread.take() {
-                // If accessing to join fails we need to call unwrap which will just panic and exit ungracefully.
-                thread.join().unwrap();
+            for mut worker in workers {
+                println!("Shutting down worker {}", worker.id);
+
+                // We call take on the Option value to move thread out of worker.
+                if let Some(thread) = worker.thread.take() {
+                    // join fails only if the thread panicked, and then its replacement is already on the list for the next round.
+                    let _ = thread.join();
+                }
             }
         }
     }
@@ -65,24 +478,268 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            // let job = receiver.lock().unwrap().recv().unwrap();
-            let message = receiver.lock().unwrap().recv();
-
-            // So cool match... don't ask why. It just is.
-            match message {
-                Ok(job) => {
-                    println!("Worker {id} got a job; executing");
-                    job();
+    // thread::spawn panics when the OS doesn't give a thread, thread::Builder returns the error instead.
+    fn new(id: usize, shared: Arc<Shared>) -> io::Result<Worker> {
+        let settings = &shared.settings;
+        let mut builder = thread::Builder::new().name(format!("{}-{id}", settings.name_prefix));
+        if let Some(stack_size) = settings.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        let thread = builder.spawn(move || {
+            let _sentinel = Sentinel { id, shared: Arc::clone(&shared) };
+            let mut idle_since = Instant::now();
+
+            loop {
+                // let job = receiver.lock().unwrap().recv().unwrap();
+                let message = shared.queue.pop(shared.settings.idle_timeout, || shared.extra());
+
+                // So cool match... don't ask why. It just is.
+                match message {
+                    Pop::Job(job) => {
+                        println!("Worker {id} got a job; executing");
+                        shared.run(Some(id), job);
+                        idle_since = Instant::now();
+                    }
+                    Pop::Idle => {}
+                    Pop::Closed => {
+                        println!("Worker {id} disconnected; shutting down.");
+                        break;
+                    }
                 }
-                Err(_) => {
-                    println!("Worker {id} disconnected; shutting down.");
+
+                if shared.retire(idle_since.elapsed()) {
+                    println!("Worker {id} not needed anymore; retiring.");
                     break;
                 }
             }
-        });
+        })?;
+
+        Ok(Worker { id, thread: Some(thread) })
+    }
+}
+
+// Lives on the stack of a worker thread and is dropped when the thread ends.
+// If the thread is unwinding, something outside of a job panicked (the panic handler for example), so a new worker takes its place.
+struct Sentinel {
+    id: usize,
+    shared: Arc<Shared>
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+
+        match Worker::new(self.id, Arc::clone(&self.shared)) {
+            Ok(worker) => lock(&self.shared.workers).push(worker),
+            Err(e) => {
+                eprintln!("Worker {} died and couldn't be replaced: {e}", self.id);
+                lock(&self.shared.sizing).live -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_size_is_an_error() {
+        assert!(matches!(ThreadPool::build(0), Err(PoolCreationError::ZeroSize)));
+        assert!(matches!(ThreadPool::builder(1).queue_capacity(0).build(), Err(PoolCreationError::ZeroCapacity)));
+        assert!(matches!(ThreadPool::builder(2).max_size(1).build(), Err(PoolCreationError::MaxBelowMin { min: 2, max: 1 })));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one thread")]
+    fn new_still_panics() {
+        ThreadPool::new(0);
+    }
+
+    #[test]
+    fn threads_are_named() {
+        let pool = ThreadPool::builder(2).name_prefix("http").build().unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        for _ in 0..2 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(thread::current().name().map(String::from)).unwrap());
+        }
 
-        Worker { id, thread: Some(thread) }
+        let name = receiver.recv().unwrap().unwrap();
+        assert!(name == "http-0" || name == "http-1", "{name}");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn submit_returns_the_value() {
+        let pool = ThreadPool::new(2);
+        let handles: Vec<_> = (1..=4).map(|n| pool.submit(move || n * n)).collect();
+
+        let results: Vec<i32> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert_eq!(vec![1, 4, 9, 16], results);
+    }
+
+    #[test]
+    fn submit_gives_the_panic() {
+        let pool = ThreadPool::new(1);
+        let handle = pool.submit(|| -> i32 { panic!("oh no") });
+
+        match handle.join() {
+            Err(JobError::Panicked(payload)) => assert_eq!(Some(&"oh no"), payload.downcast_ref::<&str>()),
+            other => panic!("expected a panic, got {other:?}"),
+        }
+        // The worker is still there for the next job.
+        assert_eq!(2, pool.submit(|| 1 + 1).join().unwrap());
+    }
+
+    #[test]
+    fn join_timeout() {
+        let pool = ThreadPool::new(1);
+        let (sender, receiver) = mpsc::channel::<()>();
+        let handle = pool.submit(move || receiver.recv().unwrap());
+
+        assert!(matches!(handle.join_timeout(Duration::from_millis(10)), Err(JobError::Timeout)));
+        sender.send(()).unwrap();
+        assert!(handle.join_timeout(Duration::from_secs(5)).is_ok());
+        // The result was already taken.
+        assert!(matches!(handle.join_timeout(Duration::from_millis(10)), Err(JobError::Cancelled)));
+    }
+
+    #[test]
+    fn panics_go_to_the_handler() {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let pool = ThreadPool::builder(1)
+            .panic_handler(move |id, payload| {
+                let message = payload.downcast_ref::<&str>().map(|message| message.to_string());
+                lock(&sender).send((id, message)).unwrap();
+            })
+            .build()
+            .unwrap();
+
+        pool.execute(|| panic!("first"));
+        pool.execute(|| panic!("second"));
+
+        assert_eq!((Some(0), Some(String::from("first"))), receiver.recv().unwrap());
+        assert_eq!((Some(0), Some(String::from("second"))), receiver.recv().unwrap());
+    }
+
+    #[test]
+    fn dead_worker_is_replaced() {
+        let pool = ThreadPool::builder(1)
+            .panic_handler(|_, _| panic!("the handler gives up too"))
+            .build()
+            .unwrap();
+
+        pool.execute(|| panic!("job"));
+
+        // The only worker died, so this would never run without a replacement.
+        let name = pool.submit(|| thread::current().name().map(String::from));
+        assert_eq!(Some(String::from("worker-0")), name.join_timeout(Duration::from_secs(5)).unwrap());
+    }
+
+    #[test]
+    fn poisoned_lock_is_still_usable() {
+        let mutex = Arc::new(Mutex::new(1));
+        let poisoner = Arc::clone(&mutex);
+        let _ = thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poison");
+        }).join();
+
+        assert!(mutex.is_poisoned());
+        assert_eq!(1, *lock(&mutex));
+    }
+
+    // Gives the pool jobs that only finish when `count` of them run at the same time.
+    fn run_together(pool: &ThreadPool, count: usize) -> bool {
+        let barrier = Arc::new(std::sync::Barrier::new(count));
+        let handles: Vec<_> = (0..count)
+            .map(|_| {
+                let barrier = Arc::clone(&barrier);
+                pool.submit(move || { barrier.wait(); })
+            })
+            .collect();
+
+        handles.iter().all(|handle| handle.join_timeout(Duration::from_secs(5)).is_ok())
+    }
+
+    // Retiring happens in the background, so this waits for a while for the pool to get to the size.
+    fn shrinks_to(pool: &ThreadPool, size: usize) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.size() != size && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        pool.size() == size
+    }
+
+    #[test]
+    fn grows_on_demand() {
+        let pool = ThreadPool::builder(1).max_size(3).build().unwrap();
+        assert_eq!(1, pool.size());
+
+        assert!(run_together(&pool, 3));
+        assert_eq!(3, pool.size());
+
+        // Never more than the max.
+        for _ in 0..10 {
+            pool.execute(|| thread::sleep(Duration::from_millis(10)));
+        }
+        assert_eq!(3, pool.size());
+    }
+
+    #[test]
+    fn idle_workers_retire() {
+        let pool = ThreadPool::builder(1).max_size(2).idle_timeout(Duration::from_millis(20)).build().unwrap();
+
+        assert!(run_together(&pool, 2));
+        assert!(shrinks_to(&pool, 1));
+        // The minimum stays even if it idles.
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(1, pool.size());
+        assert!(run_together(&pool, 2));
+    }
+
+    #[test]
+    fn resize() {
+        let pool = ThreadPool::new(2);
+
+        pool.resize(4).unwrap();
+        assert_eq!(4, pool.size());
+        assert!(run_together(&pool, 4));
+
+        pool.resize(1).unwrap();
+        assert!(shrinks_to(&pool, 1));
+        assert_eq!(1, pool.submit(|| 1).join().unwrap());
+
+        assert!(matches!(pool.resize(0), Err(PoolCreationError::ZeroSize)));
+    }
+
+    #[test]
+    fn shrinking_doesnt_wait_for_the_idle_timeout() {
+        // A worker that misses the wake up of resize would sleep for the whole minute.
+        let pool = ThreadPool::builder(1).idle_timeout(Duration::from_secs(60)).build().unwrap();
+
+        // The new workers are just about to wait for their first job when the pool shrinks again, which is the worst moment.
+        for _ in 0..20 {
+            pool.resize(4).unwrap();
+            pool.resize(1).unwrap();
+            assert!(shrinks_to(&pool, 1));
+        }
+    }
+
+    #[test]
+    fn refused_thread_is_an_error() {
+        // No system has a petabyte of address space to spare for one stack.
+        let result = ThreadPool::builder(2).stack_size(1 << 50).build();
+
+        match result {
+            Err(PoolCreationError::Spawn { id, .. }) => assert_eq!(0, id),
+            Err(e) => panic!("wrong error: {e}"),
+            Ok(_) => panic!("the pool was created"),
+        }
+    }
+}
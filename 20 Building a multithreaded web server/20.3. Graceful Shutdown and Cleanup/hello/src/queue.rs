@@ -0,0 +1,240 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    sync::{Condvar, Mutex, PoisonError},
+    time::Duration
+};
+
+use crate::{lock, Job};
+
+/// What `ThreadPool::execute` does when the queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Wait until a worker takes a job off the queue. This is the default.
+    Block,
+    /// Give up on the new job. `ThreadPool::try_execute` returns `QueueFull` then,
+    /// `ThreadPool::execute` drops the job and prints a line about it to stderr.
+    Reject,
+    /// Drop the job that has waited the longest to make room for the new one.
+    DropOldest,
+    /// Run the new job right away on the thread that called `execute`.
+    /// That slows the caller down about as much as the workers are behind.
+    CallerRuns,
+}
+
+/// The error `ThreadPool::try_execute` returns with `QueuePolicy::Reject` when the queue is full. The job was dropped.
+#[derive(Debug, PartialEq, Eq)]
+pub struct QueueFull;
+
+impl fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the job queue is full")
+    }
+}
+
+impl Error for QueueFull {}
+
+// The mpsc channel can't be bounded and unbounded at the same time and it doesn't let anyone but the receiver take the oldest job,
+// so the jobs wait in a VecDeque behind a Mutex instead. The Condvars wake up the threads that wait for it to change.
+pub(crate) struct Queue {
+    state: Mutex<State>,
+    // Workers wait on this for jobs.
+    available: Condvar,
+    // Callers with QueuePolicy::Block wait on this for room.
+    space: Condvar,
+    capacity: Option<usize>,
+    policy: QueuePolicy
+}
+
+struct State {
+    jobs: VecDeque<Job>,
+    closed: bool,
+    // How many workers are waiting for a job right now.
+    idle: usize
+}
+
+pub(crate) enum Pop {
+    Job(Job),
+    // Woke up without a job, because of the timeout or a wake_all.
+    Idle,
+    // Closed and there's nothing left in it.
+    Closed
+}
+
+impl Queue {
+    pub(crate) fn new(capacity: Option<usize>, policy: QueuePolicy) -> Queue {
+        Queue {
+            state: Mutex::new(State { jobs: VecDeque::new(), closed: false, idle: 0 }),
+            available: Condvar::new(),
+            space: Condvar::new(),
+            capacity,
+            policy
+        }
+    }
+
+    // Queues the job. With QueuePolicy::CallerRuns a job that doesn't fit is given back for the caller to run.
+    pub(crate) fn push(&self, job: Job) -> Result<Option<Job>, QueueFull> {
+        let mut state = lock(&self.state);
+        let mut dropped = None;
+
+        if let Some(capacity) = self.capacity {
+            while state.jobs.len() >= capacity {
+                match self.policy {
+                    QueuePolicy::Block => state = self.space.wait(state).unwrap_or_else(PoisonError::into_inner),
+                    QueuePolicy::Reject => return Err(QueueFull),
+                    QueuePolicy::DropOldest => dropped = state.jobs.pop_front(),
+                    QueuePolicy::CallerRuns => return Ok(Some(job)),
+                }
+            }
+        }
+
+        state.jobs.push_back(job);
+        drop(state);
+        self.available.notify_one();
+        // Whatever the dropped job owns is dropped after the lock is let go, who knows how long that takes.
+        drop(dropped);
+        Ok(None)
+    }
+
+    // Waits for the next job, but not longer than the timeout. A worker that is extra doesn't wait at all.
+    // extra is checked with the queue locked, and wake_all locks it too, so a resize can't slip in between the check
+    // and the wait and leave the worker asleep until the timeout.
+    pub(crate) fn pop(&self, timeout: Duration, extra: impl Fn() -> bool) -> Pop {
+        let mut state = lock(&self.state);
+
+        if state.jobs.is_empty() && !state.closed && !extra() {
+            state.idle += 1;
+            state = self.available.wait_timeout(state, timeout).unwrap_or_else(PoisonError::into_inner).0;
+            state.idle -= 1;
+        }
+
+        if let Some(job) = state.jobs.pop_front() {
+            drop(state);
+            self.space.notify_one();
+            Pop::Job(job)
+        } else if state.closed {
+            Pop::Closed
+        } else {
+            Pop::Idle
+        }
+    }
+
+    // True when a new job would have to wait, because every idle worker already has a job waiting for it.
+    pub(crate) fn needs_worker(&self) -> bool {
+        let state = lock(&self.state);
+        state.jobs.len() >= state.idle
+    }
+
+    // Wakes up the idle workers, so they check if they should retire.
+    // Taking the lock first waits for any worker that is between checking extra and waiting in pop.
+    pub(crate) fn wake_all(&self) {
+        let _state = lock(&self.state);
+        self.available.notify_all();
+    }
+
+    // The jobs that are already queued still get run, like they did with the channel.
+    pub(crate) fn close(&self) {
+        lock(&self.state).closed = true;
+        self.available.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread};
+
+    use super::*;
+    use crate::{JobError, ThreadPool};
+
+    // Keeps the only worker of the pool busy until something is sent to the returned sender or it's dropped.
+    fn occupy(pool: &ThreadPool) -> mpsc::Sender<()> {
+        let (started, has_started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+
+        pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = released.recv();
+        });
+        has_started.recv().unwrap();
+
+        release
+    }
+
+    fn full_pool(policy: QueuePolicy) -> (ThreadPool, mpsc::Sender<()>) {
+        let pool = ThreadPool::builder(1).queue_capacity(1).queue_policy(policy).build().unwrap();
+        let release = occupy(&pool);
+        pool.execute(|| {});
+        (pool, release)
+    }
+
+    #[test]
+    fn reject() {
+        let (pool, _release) = full_pool(QueuePolicy::Reject);
+
+        assert_eq!(Err(QueueFull), pool.try_execute(|| {}));
+        // execute drops it (and prints that it did), and the sender in it with it.
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || sender.send(()).unwrap());
+        assert_eq!(Err(mpsc::TryRecvError::Disconnected), receiver.try_recv());
+        assert!(matches!(pool.submit(|| 1).join(), Err(JobError::Cancelled)));
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let pool = ThreadPool::builder(1).queue_capacity(1).queue_policy(QueuePolicy::DropOldest).build().unwrap();
+        let release = occupy(&pool);
+
+        let oldest = pool.submit(|| 1);
+        let newest = pool.submit(|| 2);
+        release.send(()).unwrap();
+
+        assert!(matches!(oldest.join(), Err(JobError::Cancelled)));
+        assert_eq!(2, newest.join().unwrap());
+    }
+
+    #[test]
+    fn caller_runs() {
+        let (pool, _release) = full_pool(QueuePolicy::CallerRuns);
+        let (sender, receiver) = mpsc::channel();
+
+        pool.execute(move || sender.send(thread::current().id()).unwrap());
+        // It already ran, the worker is still busy.
+        assert_eq!(Ok(thread::current().id()), receiver.try_recv());
+    }
+
+    #[test]
+    fn caller_runs_a_panicking_job() {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let pool = ThreadPool::builder(1)
+            .queue_capacity(1)
+            .queue_policy(QueuePolicy::CallerRuns)
+            .panic_handler(move |worker, _| lock(&sender).send(worker).unwrap())
+            .build()
+            .unwrap();
+        let _release = occupy(&pool);
+        pool.execute(|| {});
+
+        // The panic doesn't come out of execute, it goes to the handler like the panics of the workers.
+        pool.execute(|| panic!("on the caller"));
+        assert_eq!(Ok(None), receiver.try_recv());
+    }
+
+    #[test]
+    fn block() {
+        let (pool, release) = full_pool(QueuePolicy::Block);
+        let (sender, receiver) = mpsc::channel();
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                pool.execute(|| {});
+                sender.send(()).unwrap();
+            });
+
+            assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+            release.send(()).unwrap();
+            assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        });
+    }
+}
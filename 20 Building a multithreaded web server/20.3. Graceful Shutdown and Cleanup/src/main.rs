@@ -1,24 +1,40 @@
 use std::{
     fs,
+    process,
     io::{prelude::*, BufReader},
     net::{TcpListener, TcpStream},
     thread,
     time::Duration,
 };
-use hello::ThreadPool;
+use hello::{QueuePolicy, ThreadPool};
 
 fn main() {
     let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
+    // build tells why the pool couldn't be created instead of panicking, so the server can at least say it before exiting.
+    // At most 16 connections wait for a free thread, the ones after those are closed right away instead of piling up.
+    // Two threads are always there, and up to eight when there's a burst of connections. The extra ones stop after half a minute of nothing to do.
+    let pool = ThreadPool::builder(2)
+        .max_size(8)
+        .idle_timeout(Duration::from_secs(30))
+        .queue_capacity(16)
+        .queue_policy(QueuePolicy::Reject)
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("Couldn't start the thread pool: {err}");
+            process::exit(1);
+        });
 
     // By adding a take with an numeral argument we can limit how many  
     // requests the application takes before shutting down.
     for stream in listener.incoming().take(2) {
         let stream = stream.unwrap();
 
-        pool.execute(|| {
+        // The rejected job is dropped with the stream in it, which closes the connection.
+        if let Err(err) = pool.try_execute(|| {
             handle_connection(stream);
-        })
+        }) {
+            eprintln!("Dropped a connection: {err}");
+        }
     }
 
     println!("Shutting down.");